@@ -3,6 +3,7 @@ use std::ops::RangeInclusive;
 
 use crate::parser::PARSER_COUNT;
 use chrono::{NaiveDate, NaiveDateTime, ParseResult};
+use chrono_tz::Tz;
 use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -11,6 +12,135 @@ pub enum ImageFormat {
     Jpeg,
     Jpg,
     Png,
+    Webp,
+    Avif,
+}
+
+/// encoder settings of the converted images
+#[derive(clap::Args, Clone, Debug)]
+pub struct EncodingOptions {
+    /// Encode WebP images losslessly (larger files, ignores --webp-quality)
+    #[arg(long, default_value_t = false)]
+    pub webp_lossless: bool,
+
+    /// WebP quality (0-100), 75 yields files of a similar size as the originals
+    #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub webp_quality: u8,
+
+    /// AVIF quality (1-100)
+    #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub avif_quality: u8,
+
+    /// AVIF encoding speed (1 - slowest, smallest files, 10 - fastest)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=10))]
+    pub avif_speed: u8,
+
+    /// JPEG quality (1-100)
+    #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+
+    /// PNG compression level
+    #[arg(long, value_enum, default_value_t = PngCompression::Default)]
+    pub png_compression: PngCompression,
+
+    /// Downscale images wider than this (pixels), keeps the aspect ratio. Originals are re-encoded when set
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_width: Option<u32>,
+
+    /// Downscale images taller than this (pixels), keeps the aspect ratio. Originals are re-encoded when set
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_height: Option<u32>,
+
+    /// Resampling filter used for downscaling
+    #[arg(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+    pub resize_filter: ResizeFilter,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PngCompression {
+    /// larger files, faster
+    Fast,
+    Default,
+    /// smallest files, slow
+    Best,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ResizeFilter {
+    /// nearest neighbor, blocky
+    Nearest,
+    /// linear
+    Triangle,
+    /// cubic
+    CatmullRom,
+    /// blurry
+    Gaussian,
+    /// sharpest, slowest
+    Lanczos3,
+}
+
+/// emoji which differ only in skin tone, gender or presentation, e.g. 👍 and 👍🏽
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum EmojiVariants {
+    /// separate folders, e.g. "thumbs-up-sign_medium-skin-tone"
+    Keep,
+    /// a single folder named after the base emoji
+    Merge,
+}
+
+/// what to do when several items would be exported to the same files
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    /// the last item wins
+    Overwrite,
+    /// only the first item is exported
+    Skip,
+    /// the other items get a numeric suffix, e.g. `_2`
+    Rename,
+    /// stop before exporting anything
+    Fail,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum MomentLayout {
+    /// front and back camera images as separate files
+    Separate,
+    /// the back camera image with the front camera inset, as shown in the app
+    Composite,
+    /// the back and front camera images next to each other
+    SideBySide,
+    /// the back camera image above the front camera image
+    Stacked,
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DualDirection {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InsetCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum PlanFormat {
+    Table,
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub enum TimeZoneMode {
+    /// timezone from the user.json file of the export
+    Auto,
+    Utc,
+    Named(Tz),
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -25,6 +155,16 @@ pub enum MemoriesGrouping {
     Day,
     /// subdirectories representing each calendar date identified by year, month and day, total depth: 2
     DayFlat,
+    /// subdirectories representing ISO weeks in a hierarchy "OUTPUT/week-based year/W01", total depth: 3
+    Week,
+    /// subdirectories representing days of the week ("Monday" ... "Sunday"), total depth: 2
+    Weekday,
+    /// "late" and "on-time" subdirectories
+    Late,
+    /// "with-bts" and "without-bts" (behind the scenes video) subdirectories
+    Bts,
+    /// subdirectories per Spotify track ("spotify-<track id>") and "no-song"
+    Song,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -35,6 +175,14 @@ pub enum RealmojiGrouping {
     Instant,
     /// group by the unicode emoji character associated with a realmoji in the app, total depth: 2
     Emoji,
+    /// subdirectories representing years of the creation time, total depth: 2
+    Year,
+    /// subdirectories in a hierarchy "OUTPUT/year/month", total depth: 3
+    Month,
+    /// subdirectories in a hierarchy "OUTPUT/year/month/day", total depth: 4
+    Day,
+    /// subdirectories representing each calendar date, total depth: 2
+    DayFlat,
 }
 
 #[derive(Parser, Debug)]
@@ -47,7 +195,7 @@ pub struct Args {
     #[command(subcommand)]
     pub command: Commands,
 
-    /// Unzipped BeReal data export folder path
+    /// BeReal data export path - either the downloaded .zip archive or the unzipped folder
     #[arg(short, long)]
     pub input: String,
 
@@ -55,9 +203,9 @@ pub struct Args {
     #[arg(short, long)]
     pub output: String,
 
-    #[arg(short, long, default_value_t = 0, value_parser = clap::value_parser!(u64).range(0..PARSER_COUNT))]
-    /// Export structure version
-    pub export_version: u64,
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(0..PARSER_COUNT))]
+    /// Export structure version, detected automatically when not specified
+    pub export_version: Option<u64>,
 
     /// Enable verbose output
     #[arg(short, long, default_value_t = false)]
@@ -70,6 +218,42 @@ pub struct Args {
     /// disable metadata export for images
     #[arg(long, default_value_t = false)]
     pub no_meta: bool,
+
+    /// Write an XMP sidecar (`.xmp`) next to each exported file, ignored with --no-meta
+    #[arg(long, default_value_t = false)]
+    pub xmp: bool,
+
+    /// Keep the EXIF metadata of the original images, only the description, time and location are updated
+    #[arg(long, default_value_t = false)]
+    pub merge_metadata: bool,
+
+    /// Remove all metadata from the exported files and write none, e.g. for sharing
+    #[arg(long, default_value_t = false, conflicts_with_all = ["merge_metadata", "xmp"])]
+    pub strip_metadata: bool,
+
+    /// Set the access and modification times of the exported files to the capture time (local time zone corrected)
+    #[arg(long, default_value_t = false)]
+    pub set_file_times: bool,
+
+    /// Policy for items exported to the same file names, e.g. moments taken within the same second
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Rename)]
+    pub on_conflict: ConflictPolicy,
+
+    /// Re-export all files, even those the output manifest lists as up to date
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// Timezone used for file names, folders and metadata: "auto" (from the export's user.json), "utc" or an IANA name (e.g. Europe/Prague)
+    #[arg(long, default_value = "auto", value_parser = parse_time_zone)]
+    pub time_zone: TimeZoneMode,
+
+    /// Write a JSON report of the entries that could not be (fully) exported to this file
+    #[arg(long)]
+    pub report: Option<String>,
+
+    /// Only print the planned exports (as a table or JSON), nothing is written to the disk
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "table")]
+    pub dry_run: Option<PlanFormat>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -81,17 +265,27 @@ pub enum Commands {
         #[clap(value_enum, default_value_t=ImageFormat::None)]
         image_format: ImageFormat,
 
-        /// Groups images and videos, defines the filesystem structure inside the OUTPUT folder
-        #[arg(short, long)]
-        #[clap(value_enum, default_value_t=MemoriesGrouping::None)]
-        group: MemoriesGrouping,
+        #[command(flatten)]
+        encoding: EncodingOptions,
+
+        /// Groups images and videos, defines the filesystem structure inside the OUTPUT folder.
+        /// Comma-separated groupings nest in the given order, e.g. "year,late" exports to "OUTPUT/2023/late"
+        #[arg(short, long, value_delimiter = ',')]
+        #[clap(value_enum, default_value = "none")]
+        group: Vec<MemoriesGrouping>,
+
+        /// Folder and file name template used instead of --group, e.g. "{year}/{month:02}-{month_name}/{date}_{time}_{camera}{late?_late}".
+        /// Placeholders: {year}, {month}, {day}, {hour}, {minute}, {second}, {week}, {week_year} (numbers, {month:02} pads with zeros), {month_name}, {weekday},
+        /// {date}, {time}, {camera} (the file, e.g. camera_front or BTS, appended when missing) and the flags {late?text:other}, {bts?..}, {song?..}
+        #[arg(long, conflicts_with = "group")]
+        template: Option<String>,
 
         /// Caption regular expression filter
         #[arg(short, long)]
         caption: Option<String>,
 
         /// Time filter list. Specify a pairs of values separated by comma. Each pair is separated the plus sign (+).
-        /// Each value shall be in the form of either YYYY-MM-DDTHH:mm:SS or YYYY-MM-DD. Order within the list and pairs is irrelevant.
+        /// Each value shall be in the form of either YYYY-MM-DDTHH:mm:SS or YYYY-MM-DD (in the selected time zone). Order within the list and pairs is irrelevant.
         /// (example: 2024-02-10+2022-01-19T13:51:00,2021-08-19T20:11:32+2021-09-20T20:11:32 will search within 2 time intervals)
         #[arg(short = 't', long, value_parser = parse_interval_vec)]
         interval: Option<std::vec::Vec<TimeInterval>>,
@@ -103,6 +297,38 @@ pub enum Commands {
         /// EXIF metadata description suffix
         #[arg(long, default_value_t=("".to_string()))]
         desc_suffix: String,
+
+        /// Do not export the location (GPS) of memories, e.g. when sharing the export
+        #[arg(long, default_value_t = false)]
+        no_location: bool,
+
+        /// Export the front and back camera images separately or as a single composite image
+        #[arg(long, value_enum, default_value_t = MomentLayout::Separate)]
+        layout: MomentLayout,
+
+        /// Corner of the front camera inset (composite layout)
+        #[arg(long, value_enum, default_value_t = InsetCorner::TopLeft)]
+        inset_corner: InsetCorner,
+
+        /// Width of the front camera inset in percent of the back camera image width (composite layout)
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u8).range(1..=100))]
+        inset_size: u8,
+
+        /// Border width of the front camera inset in pixels (composite layout)
+        #[arg(long, default_value_t = 6)]
+        inset_border: u32,
+
+        /// Corner radius of the front camera inset in pixels (composite layout)
+        #[arg(long, default_value_t = 48)]
+        inset_radius: u32,
+
+        /// Space between the images in pixels (side-by-side and stacked layouts)
+        #[arg(long, default_value_t = 0)]
+        gutter: u32,
+
+        /// Background (gutter) color as a hex RGB value, e.g. "#ffffff" (side-by-side and stacked layouts)
+        #[arg(long, default_value = "#000000", value_parser = parse_color)]
+        background: [u8; 3],
     },
 
     /// Export RealMojis
@@ -112,10 +338,24 @@ pub enum Commands {
         #[clap(value_enum, default_value_t=ImageFormat::None)]
         image_format: ImageFormat,
 
-        /// Groups images and videos, defines the filesystem structure inside the OUTPUT folder
-        #[arg(short, long)]
-        #[clap(value_enum, default_value_t=RealmojiGrouping::None)]
-        group: RealmojiGrouping,
+        #[command(flatten)]
+        encoding: EncodingOptions,
+
+        /// Groups images, defines the filesystem structure inside the OUTPUT folder.
+        /// Comma-separated groupings nest in the given order, e.g. "emoji,year" exports to "OUTPUT/emoji name/2023"
+        #[arg(short, long, value_delimiter = ',')]
+        #[clap(value_enum, default_value = "none")]
+        group: Vec<RealmojiGrouping>,
+
+        /// Folder and file name template used instead of --group, e.g. "{emoji}/{year}/realmoji-{index}{instant?_instant}".
+        /// Placeholders: {year}, {month}, {day}, {hour}, {minute}, {second}, {week}, {week_year}, {index} (numbers, {month:02} pads with zeros), {month_name},
+        /// {weekday}, {date}, {time}, {emoji} and the flag {instant?text:other}
+        #[arg(long, conflicts_with = "group")]
+        template: Option<String>,
+
+        /// Emoji variants (skin tones, gender signs) in the emoji folder names: kept apart or merged into the base emoji
+        #[arg(long, value_enum, default_value_t = EmojiVariants::Keep)]
+        emoji_variants: EmojiVariants,
 
         /// EXIF metadata description prefix
         #[arg(long, default_value_t=("".to_string()))]
@@ -178,6 +418,31 @@ fn parse_interval(arg: &str) -> Result<TimeInterval, String> {
         to: time_objs[1],
     })
 }
+
+fn parse_color(arg: &str) -> Result<[u8; 3], String> {
+    let hex = arg.strip_prefix('#').unwrap_or(arg);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("invalid color `{arg}`, expected e.g. #ff8000"));
+    }
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("invalid color `{arg}`, expected e.g. #ff8000"))?;
+    }
+    Ok(color)
+}
+
+fn parse_time_zone(arg: &str) -> Result<TimeZoneMode, String> {
+    match arg.to_lowercase().as_str() {
+        "auto" => Ok(TimeZoneMode::Auto),
+        "utc" => Ok(TimeZoneMode::Utc),
+        _ => arg
+            .parse::<Tz>()
+            .map(TimeZoneMode::Named)
+            .map_err(|e| format!("unknown time zone `{arg}`: {e}")),
+    }
+}
+
 const PARA_RANGE: RangeInclusive<usize> = 0..=100;
 
 fn para_in_range(s: &str) -> Result<u8, String> {
@@ -252,4 +517,23 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap().len(), 2)
     }
+
+    #[test]
+    fn parses_time_zones() {
+        assert!(matches!(parse_time_zone("auto"), Ok(TimeZoneMode::Auto)));
+        assert!(matches!(parse_time_zone("UTC"), Ok(TimeZoneMode::Utc)));
+        assert!(matches!(
+            parse_time_zone("America/New_York"),
+            Ok(TimeZoneMode::Named(chrono_tz::America::New_York))
+        ));
+        assert!(parse_time_zone("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000"), Ok([255, 128, 0]));
+        assert_eq!(parse_color("FFFFFF"), Ok([255, 255, 255]));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("red").is_err());
+    }
 }
@@ -0,0 +1,210 @@
+use image::{imageops, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::args::{DualDirection, InsetCorner};
+
+/// distance of the inset from the image edges, relative to the back image width
+const INSET_MARGIN: f32 = 0.03;
+const BORDER_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// how the front and back camera images of a moment are exported
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Layout {
+    /// two files, `_camera_front` and `_camera_back`
+    Separate,
+    /// the back image with the front camera inset, as shown in the app
+    Inset(InsetOptions),
+    /// the back and front images next to each other
+    Dual(DualOptions),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InsetOptions {
+    pub corner: InsetCorner,
+    /// inset width, percentage of the back image width
+    pub size: u8,
+    /// border width in pixels
+    pub border: u32,
+    /// corner radius of the front image in pixels (the border follows it)
+    pub radius: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DualOptions {
+    pub direction: DualDirection,
+    /// space between the images in pixels
+    pub gutter: u32,
+    /// RGB color of the gutter
+    pub background: [u8; 3],
+}
+
+/// draws the front camera image into a corner of the back camera image
+pub fn compose_inset(back: &RgbaImage, front: &RgbaImage, options: &InsetOptions) -> RgbaImage {
+    let width = (back.width() * options.size as u32 / 100).max(1);
+    let height = (front.height() as u64 * width as u64 / front.width().max(1) as u64).max(1) as u32;
+    let front = imageops::resize(front, width, height, imageops::FilterType::CatmullRom);
+
+    let border = options.border;
+    let outer_radius = (options.radius + border) as f32;
+    let mut inset = RgbaImage::new(width + 2 * border, height + 2 * border);
+    let (outer_w, outer_h) = inset.dimensions();
+    for (x, y, pixel) in inset.enumerate_pixels_mut() {
+        let outer = rounded_rect_coverage(
+            x as f32 + 0.5,
+            y as f32 + 0.5,
+            outer_w as f32,
+            outer_h as f32,
+            outer_radius,
+        );
+        if outer == 0.0 {
+            continue;
+        }
+        let inner = rounded_rect_coverage(
+            x as f32 + 0.5 - border as f32,
+            y as f32 + 0.5 - border as f32,
+            width as f32,
+            height as f32,
+            options.radius as f32,
+        );
+        let mut color = if inner > 0.0 {
+            let front_pixel = front.get_pixel(
+                x.saturating_sub(border).min(width - 1),
+                y.saturating_sub(border).min(height - 1),
+            );
+            mix(BORDER_COLOR, *front_pixel, inner)
+        } else {
+            BORDER_COLOR
+        };
+        color[3] = (color[3] as f32 * outer).round() as u8;
+        *pixel = color;
+    }
+
+    let margin = (back.width() as f32 * INSET_MARGIN).round() as i64;
+    let right = back.width() as i64 - outer_w as i64 - margin;
+    let bottom = back.height() as i64 - outer_h as i64 - margin;
+    let (x, y) = match options.corner {
+        InsetCorner::TopLeft => (margin, margin),
+        InsetCorner::TopRight => (right, margin),
+        InsetCorner::BottomLeft => (margin, bottom),
+        InsetCorner::BottomRight => (right, bottom),
+    };
+
+    let mut result = back.clone();
+    imageops::overlay(&mut result, &inset, x, y);
+    result
+}
+
+/// places the back camera image first (left or top), the front image is scaled to the same height or width
+pub fn compose_dual(back: &RgbaImage, front: &RgbaImage, options: &DualOptions) -> RgbaImage {
+    let (width, height) = back.dimensions();
+    let (front_width, front_height) = match options.direction {
+        DualDirection::Horizontal => (
+            (front.width() as u64 * height as u64 / front.height().max(1) as u64).max(1) as u32,
+            height,
+        ),
+        DualDirection::Vertical => (
+            width,
+            (front.height() as u64 * width as u64 / front.width().max(1) as u64).max(1) as u32,
+        ),
+    };
+    let front = if front.dimensions() == (front_width, front_height) {
+        front.clone()
+    } else {
+        imageops::resize(
+            front,
+            front_width,
+            front_height,
+            imageops::FilterType::CatmullRom,
+        )
+    };
+
+    let [r, g, b] = options.background;
+    let (canvas_width, canvas_height, x, y) = match options.direction {
+        DualDirection::Horizontal => (
+            width + options.gutter + front_width,
+            height,
+            width + options.gutter,
+            0,
+        ),
+        DualDirection::Vertical => (
+            width,
+            height + options.gutter + front_height,
+            0,
+            height + options.gutter,
+        ),
+    };
+    let mut result = RgbaImage::from_pixel(canvas_width, canvas_height, Rgba([r, g, b, 255]));
+    imageops::overlay(&mut result, back, 0, 0);
+    imageops::overlay(&mut result, &front, x as i64, y as i64);
+    result
+}
+
+/// how much of the pixel centered at `x`, `y` lies inside the rounded rectangle at the origin (0 - 1)
+fn rounded_rect_coverage(x: f32, y: f32, width: f32, height: f32, radius: f32) -> f32 {
+    if x < 0.0 || y < 0.0 || x > width || y > height {
+        return 0.0;
+    }
+    let radius = radius.min(width / 2.0).min(height / 2.0);
+    // distance from the nearest corner circle center, zero outside of the corner areas
+    let dx = x - x.clamp(radius, width - radius);
+    let dy = y - y.clamp(radius, height - radius);
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance == 0.0 {
+        1.0
+    } else {
+        (radius - distance + 0.5).clamp(0.0, 1.0)
+    }
+}
+
+fn mix(from: Rgba<u8>, to: Rgba<u8>, ratio: f32) -> Rgba<u8> {
+    let mut result = from;
+    for (channel, (a, b)) in result.0.iter_mut().zip(from.0.iter().zip(to.0.iter())) {
+        *channel = (*a as f32 * (1.0 - ratio) + *b as f32 * ratio).round() as u8;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_bordered_inset_into_corner() {
+        let back = RgbaImage::from_pixel(200, 300, Rgba([255, 255, 255, 255]));
+        let front = RgbaImage::from_pixel(100, 150, Rgba([255, 0, 0, 255]));
+        let options = InsetOptions {
+            corner: InsetCorner::TopRight,
+            size: 25,
+            border: 2,
+            radius: 5,
+        };
+        let result = compose_inset(&back, &front, &options);
+
+        // 50x75 inset + 2px border, 6px margin from the top right corner
+        assert_eq!(*result.get_pixel(150, 30), Rgba([255, 0, 0, 255]));
+        assert_eq!(*result.get_pixel(140, 30), Rgba([0, 0, 0, 255]));
+        assert_eq!(*result.get_pixel(138, 30), Rgba([255, 255, 255, 255]));
+        // rounded corner leaves the background visible
+        assert_eq!(*result.get_pixel(140, 6), Rgba([255, 255, 255, 255]));
+        assert_eq!(*result.get_pixel(20, 30), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn stacks_images_with_gutter() {
+        let back = RgbaImage::from_pixel(100, 50, Rgba([255, 255, 255, 255]));
+        let front = RgbaImage::from_pixel(50, 25, Rgba([255, 0, 0, 255]));
+        let options = DualOptions {
+            direction: DualDirection::Vertical,
+            gutter: 10,
+            background: [0, 0, 255],
+        };
+        let result = compose_dual(&back, &front, &options);
+
+        // the front image is scaled to the back image width
+        assert_eq!(result.dimensions(), (100, 110));
+        assert_eq!(*result.get_pixel(50, 49), Rgba([255, 255, 255, 255]));
+        assert_eq!(*result.get_pixel(50, 55), Rgba([0, 0, 255, 255]));
+        assert_eq!(*result.get_pixel(99, 109), Rgba([255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,112 @@
+//! Readable names of emoji (grapheme clusters), e.g. for realmoji folders.
+
+use unic_ucd_name::Name;
+
+const ZWJ: char = '\u{200d}';
+const KEYCAP: char = '\u{20e3}';
+const BLACK_FLAG: char = '\u{1f3f4}';
+const CANCEL_TAG: char = '\u{e007f}';
+
+fn is_variation_selector(c: char) -> bool {
+    matches!(c, '\u{fe0e}' | '\u{fe0f}')
+}
+
+fn skin_tone(c: char) -> Option<&'static str> {
+    match c {
+        '\u{1f3fb}' => Some("light-skin-tone"),
+        '\u{1f3fc}' => Some("medium-light-skin-tone"),
+        '\u{1f3fd}' => Some("medium-skin-tone"),
+        '\u{1f3fe}' => Some("medium-dark-skin-tone"),
+        '\u{1f3ff}' => Some("dark-skin-tone"),
+        _ => None,
+    }
+}
+
+fn regional_indicator(c: char) -> Option<char> {
+    let offset = (c as u32).checked_sub(0x1f1e6).filter(|o| *o < 26)?;
+    char::from_u32('a' as u32 + offset)
+}
+
+fn tag_letter(c: char) -> Option<char> {
+    let offset = (c as u32)
+        .checked_sub(0xe0000)
+        .filter(|o| (0x20..0x7f).contains(o))?;
+    char::from_u32(offset).map(|c| c.to_ascii_lowercase())
+}
+
+/// folder name of an emoji: the Unicode names of its characters, e.g. `thumbs-up-sign_medium-skin-tone`,
+/// `heavy-black-heart_fire` (❤️‍🔥) or `flag-cz`
+pub fn folder_name(emoji: &str) -> String {
+    let chars = emoji.chars().collect::<Vec<_>>();
+
+    let country = chars
+        .iter()
+        .map(|c| regional_indicator(*c))
+        .collect::<Option<String>>();
+    if let Some(country) = country.filter(|c| c.len() == 2) {
+        return format!("flag-{country}");
+    }
+    if let [BLACK_FLAG, tags @ .., CANCEL_TAG] = chars.as_slice() {
+        if let Some(region) = tags
+            .iter()
+            .map(|c| tag_letter(*c))
+            .collect::<Option<String>>()
+        {
+            return format!("flag-{region}");
+        }
+    }
+
+    let name = chars
+        .iter()
+        .filter(|c| **c != ZWJ && !is_variation_selector(**c))
+        .filter_map(|c| match (skin_tone(*c), *c) {
+            (Some(tone), _) => Some(tone.to_owned()),
+            (None, KEYCAP) => Some("keycap".to_owned()),
+            (None, c) => Name::of(c).map(|n| n.to_string().to_lowercase().replace(' ', "-")),
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    if name.is_empty() {
+        "unknown-character".to_owned()
+    } else {
+        name
+    }
+}
+
+/// the emoji without its variants: skin tones, gender signs and text/emoji presentation selectors
+pub fn base_emoji(emoji: &str) -> String {
+    let mut result = String::new();
+    let mut chars = emoji.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ZWJ && matches!(chars.peek(), Some('\u{2640}' | '\u{2642}')) {
+            chars.next();
+            continue;
+        }
+        if skin_tone(c).is_none() && !is_variation_selector(c) {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_emoji_sequences() {
+        assert_eq!(folder_name("😃"), "smiling-face-with-open-mouth");
+        assert_eq!(folder_name("👍🏽"), "thumbs-up-sign_medium-skin-tone");
+        assert_eq!(folder_name("❤️\u{200d}🔥"), "heavy-black-heart_fire");
+        assert_eq!(folder_name("🇨🇿"), "flag-cz");
+        assert_eq!(
+            folder_name("🏴\u{e0067}\u{e0062}\u{e0073}\u{e0063}\u{e0074}\u{e007f}"),
+            "flag-gbsct"
+        );
+        assert_eq!(folder_name("1️⃣"), "digit-one_keycap");
+
+        assert_eq!(base_emoji("👍🏽"), "👍");
+        assert_eq!(base_emoji("🤷🏻\u{200d}♀️"), "🤷");
+        assert_eq!(base_emoji("❤️\u{200d}🔥"), "❤\u{200d}🔥");
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::group::Collision;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum BerealError {
+    /// the input is not a (supported) BeReal export
+    InvalidInput {
+        message: String,
+    },
+    /// invalid value supplied on the command line
+    InvalidArgument {
+        message: String,
+    },
+    MissingFile {
+        path: PathBuf,
+    },
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    /// invalid JSON file or JSON entry
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    Decode {
+        path: PathBuf,
+        message: String,
+    },
+    Encode {
+        path: PathBuf,
+        message: String,
+    },
+    Metadata {
+        path: PathBuf,
+        message: String,
+    },
+    /// several items would be exported to the same files
+    Conflict {
+        path: PathBuf,
+        count: usize,
+    },
+}
+
+impl BerealError {
+    pub fn io(path: &Path, e: io::Error) -> BerealError {
+        if e.kind() == io::ErrorKind::NotFound {
+            BerealError::MissingFile {
+                path: path.to_path_buf(),
+            }
+        } else {
+            BerealError::Io {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            }
+        }
+    }
+
+    pub fn parse<E: fmt::Display>(path: &Path, e: E) -> BerealError {
+        BerealError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BerealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BerealError::InvalidInput { message } => write!(f, "Invalid input: {}", message),
+            BerealError::InvalidArgument { message } => {
+                write!(f, "Invalid argument: {}", message)
+            }
+            BerealError::MissingFile { path } => {
+                write!(f, "File {} does not exist", path.to_string_lossy())
+            }
+            BerealError::Io { path, message } => {
+                write!(
+                    f,
+                    "I/O error, file {} - {}",
+                    path.to_string_lossy(),
+                    message
+                )
+            }
+            BerealError::Parse { path, message } => {
+                write!(
+                    f,
+                    "Parse error, file {} - {}",
+                    path.to_string_lossy(),
+                    message
+                )
+            }
+            BerealError::Decode { path, message } => {
+                write!(f, "Cannot decode {} - {}", path.to_string_lossy(), message)
+            }
+            BerealError::Encode { path, message } => {
+                write!(f, "Cannot encode {} - {}", path.to_string_lossy(), message)
+            }
+            BerealError::Metadata { path, message } => {
+                write!(
+                    f,
+                    "Metadata not written to {} - {}",
+                    path.to_string_lossy(),
+                    message
+                )
+            }
+            BerealError::Conflict { path, count } => {
+                write!(
+                    f,
+                    "{} items would be exported as {}",
+                    count,
+                    path.to_string_lossy()
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemStatus {
+    /// nothing was exported
+    Skipped,
+    /// some of the files were exported
+    Partial,
+}
+
+/// a moment or realmoji which was not (fully) exported
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemReport {
+    /// identifies the moment/realmoji
+    pub item: String,
+    pub status: ItemStatus,
+    pub errors: Vec<BerealError>,
+}
+
+/// machine-readable summary of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub command: String,
+    /// number of items after parsing (and filtering)
+    pub total: usize,
+    /// number of fully exported items
+    pub exported: usize,
+    pub items: Vec<ItemReport>,
+    /// items sharing output file names
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub collisions: Vec<Collision>,
+}
+
+impl Report {
+    pub fn write(&self, path: &Path) -> Result<(), BerealError> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| BerealError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        std::fs::write(path, json).map_err(|e| BerealError::io(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_report_items() {
+        let item = ItemReport {
+            item: "2024/2024-01-07T07-00-00".to_string(),
+            status: ItemStatus::Partial,
+            errors: vec![BerealError::MissingFile {
+                path: PathBuf::from("Photos/post/a.webp"),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&item).unwrap(),
+            r#"{"item":"2024/2024-01-07T07-00-00","status":"partial","errors":[{"kind":"missing-file","path":"Photos/post/a.webp"}]}"#
+        );
+    }
+}
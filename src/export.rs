@@ -1,57 +1,474 @@
-use crate::{args::ImageFormat, BerealBTSData, OutputMomentSpec, OutputRealmojiSpec};
-use chrono::NaiveDateTime;
-use image::ImageReader;
+use crate::{
+    args::DualDirection,
+    args::{
+        ConflictPolicy, EncodingOptions, ImageFormat, PlanFormat, PngCompression, ResizeFilter,
+    },
+    composite::{compose_dual, compose_inset, DualOptions, InsetOptions, Layout},
+    error::{BerealError, ItemReport, ItemStatus},
+    group::{Collision, OutputName},
+    manifest::Manifest,
+    mp4,
+    source::InputSource,
+    xmp, BerealBTSData, BerealLocation, OutputMomentSpec, OutputRealmojiSpec,
+};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use clap::ValueEnum;
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+    },
+    imageops::FilterType,
+    DynamicImage, ImageReader, RgbaImage,
+};
 use img_parts::{Bytes, DynImage, ImageEXIF};
+use itertools::Itertools;
+use little_exif::metadata::Metadata;
+use regex::Regex;
+use serde::Serialize;
 use std::{
+    borrow::Cow,
     cmp::max,
-    fmt::Display,
-    fs::{self, canonicalize, File},
-    io::{self, BufWriter, Write},
+    collections::HashSet,
+    fs::{self, File, FileTimes},
+    io::{self, BufWriter, Cursor, Write},
     path::{absolute, Path, PathBuf},
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, Arc, Mutex},
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 #[derive(Clone, Debug)]
 pub struct ImageMetadata {
     caption: Option<String>,
     time_taken: NaiveDateTime,
+    utc_offset: FixedOffset,
+    location: Option<BerealLocation>,
+    /// update the EXIF of the original image instead of replacing it
+    merge_existing: bool,
 }
 
 pub enum ExportJobSpec {
     ImageConvert {
         /// filename WITHOUT the extension
         output_file_name: String,
-        /// image to convert
+        /// image to convert, relative to the input root
         original_image_path: PathBuf,
         output_format: ImageFormat,
+        encoding: EncodingOptions,
         metadata: Option<ImageMetadata>,
+        /// remove the metadata of the original (only relevant for copies, converted images have none)
+        strip_metadata: bool,
     },
     Copy {
         /// where to copy to, WITHOUT file extension (will be copied from the original)
         output_file_name: String,
-        /// where to copy from
+        /// where to copy from, relative to the input root
         original_path: PathBuf,
-        // TODO: curetnly only videos are copied, metadata is not needed here
-        // in any case, little_exif does not support mp4 anyway yet
+        /// written into MP4/MOV videos, other files are copied as-is
+        metadata: Option<ImageMetadata>,
+        /// remove the metadata of MP4/MOV videos
+        strip_metadata: bool,
+    },
+    /// the back camera image with the front camera inset
+    Composite {
+        /// filename WITHOUT the extension
+        output_file_name: String,
+        /// both relative to the input root
+        back_image_path: PathBuf,
+        front_image_path: PathBuf,
+        /// `None` keeps the format of the back image
+        output_format: ImageFormat,
+        encoding: EncodingOptions,
+        metadata: Option<ImageMetadata>,
+        inset: InsetOptions,
+    },
+    /// the back and front camera images next to each other (or stacked)
+    DualCamera {
+        /// filename WITHOUT the extension
+        output_file_name: String,
+        /// both relative to the input root
+        back_image_path: PathBuf,
+        front_image_path: PathBuf,
+        /// `None` keeps the format of the back image
+        output_format: ImageFormat,
+        encoding: EncodingOptions,
+        metadata: Option<ImageMetadata>,
+        options: DualOptions,
+    },
+    /// XMP sidecar of another exported file
+    Sidecar {
+        /// filename WITHOUT the extension, the same as the described file
+        output_file_name: String,
+        /// file names of the other files exported from the same item
+        related: Vec<String>,
+        keywords: Vec<String>,
+        metadata: ImageMetadata,
     },
 }
 
+/// a single planned file operation, see `plan_generic`
+#[derive(Debug, Serialize)]
+pub struct PlannedJob {
+    /// `convert`, `copy`, `composite` or `dual-camera`
+    pub action: &'static str,
+    pub sources: Vec<PathBuf>,
+    pub destination: PathBuf,
+    /// target image format, `None` for plain copies
+    pub format: Option<String>,
+    /// encoder settings of the target format, if it has any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    pub metadata: Option<PlannedMetadata>,
+    /// how the images are combined, only for jobs with multiple sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    /// the metadata of the original is removed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strip_metadata: bool,
+    /// sidecars only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// sidecars only, other files of the same item
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<String>,
+    /// another planned job writes the same destination (unresolved collision)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub conflict: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedMetadata {
+    pub caption: Option<String>,
+    /// local time, without the offset
+    pub time_taken: String,
+    pub utc_offset: String,
+    pub location: Option<BerealLocation>,
+    /// merged into the metadata of the original
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub merge: bool,
+}
+
+impl From<ImageMetadata> for PlannedMetadata {
+    fn from(meta: ImageMetadata) -> Self {
+        PlannedMetadata {
+            caption: meta.caption.filter(|c| !c.is_empty()),
+            time_taken: meta.time_taken.format("%Y-%m-%d %H:%M:%S").to_string(),
+            utc_offset: meta.utc_offset.to_string(),
+            location: meta.location,
+            merge: meta.merge_existing,
+        }
+    }
+}
+
+impl PlannedJob {
+    /// everything but the source and destination affecting the output file
+    fn options(&self) -> String {
+        serde_json::to_string(&(
+            self.action,
+            &self.format,
+            &self.encoding,
+            &self.metadata,
+            &self.layout,
+            &self.strip_metadata,
+            &self.keywords,
+            &self.related,
+        ))
+        .unwrap_or_default()
+    }
+}
+
+impl ExportJobSpec {
+    /// relative to the input root
+    fn sources(&self) -> Vec<&Path> {
+        match self {
+            ExportJobSpec::ImageConvert {
+                original_image_path,
+                ..
+            } => vec![original_image_path],
+            ExportJobSpec::Copy { original_path, .. } => vec![original_path],
+            ExportJobSpec::Composite {
+                back_image_path,
+                front_image_path,
+                ..
+            }
+            | ExportJobSpec::DualCamera {
+                back_image_path,
+                front_image_path,
+                ..
+            } => vec![back_image_path, front_image_path],
+            ExportJobSpec::Sidecar { .. } => vec![],
+        }
+    }
+
+    /// output file name including the extension
+    fn file_name(&self) -> String {
+        let (name, extension) = match self {
+            ExportJobSpec::ImageConvert {
+                output_file_name,
+                original_image_path,
+                output_format,
+                encoding,
+                ..
+            } => (
+                output_file_name,
+                conversion_format(output_format, original_image_path, encoding).0,
+            ),
+            ExportJobSpec::Copy {
+                output_file_name,
+                original_path,
+                ..
+            } => (
+                output_file_name,
+                original_path
+                    .extension()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .unwrap_or("unknown".to_owned()),
+            ),
+            ExportJobSpec::Composite {
+                output_file_name,
+                back_image_path,
+                output_format,
+                ..
+            }
+            | ExportJobSpec::DualCamera {
+                output_file_name,
+                back_image_path,
+                output_format,
+                ..
+            } => (
+                output_file_name,
+                composite_format(output_format, back_image_path).0,
+            ),
+            ExportJobSpec::Sidecar {
+                output_file_name, ..
+            } => (output_file_name, "xmp".to_owned()),
+        };
+        name.clone() + "." + &extension
+    }
+
+    fn metadata(&self) -> Option<&ImageMetadata> {
+        match self {
+            ExportJobSpec::ImageConvert { metadata, .. }
+            | ExportJobSpec::Composite { metadata, .. }
+            | ExportJobSpec::DualCamera { metadata, .. } => metadata.as_ref(),
+            ExportJobSpec::Copy {
+                metadata,
+                original_path,
+                ..
+            } => metadata.as_ref().filter(|_| is_video(original_path)),
+            ExportJobSpec::Sidecar { .. } => None,
+        }
+    }
+
+    fn plan(&self, input: &InputSource, output_folder: &Path) -> PlannedJob {
+        match self {
+            ExportJobSpec::ImageConvert {
+                original_image_path,
+                output_format,
+                encoding,
+                metadata,
+                strip_metadata,
+                ..
+            } => {
+                let (extension, lib_format) =
+                    conversion_format(output_format, original_image_path, encoding);
+                PlannedJob {
+                    action: if lib_format.is_some() {
+                        "convert"
+                    } else {
+                        "copy"
+                    },
+                    sources: vec![input.display_path(original_image_path)],
+                    destination: output_folder.join(self.file_name()),
+                    format: lib_format.map(|_| extension),
+                    encoding: lib_format.and_then(|f| encoder_settings(f, encoding)),
+                    metadata: metadata.clone().map(PlannedMetadata::from),
+                    layout: None,
+                    strip_metadata: *strip_metadata && lib_format.is_none(),
+                    keywords: vec![],
+                    related: vec![],
+                    conflict: false,
+                }
+            }
+            ExportJobSpec::Copy {
+                original_path,
+                metadata,
+                strip_metadata,
+                ..
+            } => PlannedJob {
+                action: "copy",
+                sources: vec![input.display_path(original_path)],
+                destination: output_folder.join(self.file_name()),
+                format: None,
+                encoding: None,
+                metadata: metadata
+                    .clone()
+                    .filter(|_| is_video(original_path))
+                    .map(PlannedMetadata::from),
+                layout: None,
+                strip_metadata: *strip_metadata && is_video(original_path),
+                keywords: vec![],
+                related: vec![],
+                conflict: false,
+            },
+            ExportJobSpec::Composite {
+                back_image_path,
+                front_image_path,
+                output_format,
+                encoding,
+                metadata,
+                inset,
+                ..
+            } => {
+                let (extension, lib_format) = composite_format(output_format, back_image_path);
+                PlannedJob {
+                    action: "composite",
+                    sources: vec![
+                        input.display_path(back_image_path),
+                        input.display_path(front_image_path),
+                    ],
+                    destination: output_folder.join(self.file_name()),
+                    format: Some(extension),
+                    encoding: lib_format.and_then(|f| encoder_settings(f, encoding)),
+                    metadata: metadata.clone().map(PlannedMetadata::from),
+                    layout: Some(Layout::Inset(inset.clone())),
+                    strip_metadata: false,
+                    keywords: vec![],
+                    related: vec![],
+                    conflict: false,
+                }
+            }
+            ExportJobSpec::DualCamera {
+                back_image_path,
+                front_image_path,
+                output_format,
+                encoding,
+                metadata,
+                options,
+                ..
+            } => {
+                let (extension, lib_format) = composite_format(output_format, back_image_path);
+                PlannedJob {
+                    action: "dual-camera",
+                    sources: vec![
+                        input.display_path(back_image_path),
+                        input.display_path(front_image_path),
+                    ],
+                    destination: output_folder.join(self.file_name()),
+                    format: Some(extension),
+                    encoding: lib_format.and_then(|f| encoder_settings(f, encoding)),
+                    metadata: metadata.clone().map(PlannedMetadata::from),
+                    layout: Some(Layout::Dual(options.clone())),
+                    strip_metadata: false,
+                    keywords: vec![],
+                    related: vec![],
+                    conflict: false,
+                }
+            }
+            ExportJobSpec::Sidecar {
+                related,
+                keywords,
+                metadata,
+                ..
+            } => PlannedJob {
+                action: "sidecar",
+                sources: vec![],
+                destination: output_folder.join(self.file_name()),
+                format: Some("xmp".to_owned()),
+                encoding: None,
+                metadata: Some(metadata.clone().into()),
+                layout: None,
+                strip_metadata: false,
+                keywords: keywords.clone(),
+                related: related.clone(),
+                conflict: false,
+            },
+        }
+    }
+}
+
+/// adds an XMP sidecar for every job writing metadata, linking it to the other files of the item
+fn with_sidecars(mut jobs: Vec<ExportJobSpec>, keywords: Vec<String>) -> Vec<ExportJobSpec> {
+    let file_names = jobs.iter().map(|job| job.file_name()).collect::<Vec<_>>();
+    let sidecars = jobs
+        .iter()
+        .zip(&file_names)
+        .filter_map(|(job, file_name)| {
+            let metadata = job.metadata()?.clone();
+            Some(ExportJobSpec::Sidecar {
+                output_file_name: Path::new(file_name)
+                    .file_stem()?
+                    .to_string_lossy()
+                    .to_string(),
+                related: file_names
+                    .iter()
+                    .filter(|other| *other != file_name)
+                    .cloned()
+                    .collect(),
+                keywords: keywords.clone(),
+                metadata,
+            })
+        })
+        .collect::<Vec<_>>();
+    jobs.extend(sidecars);
+    jobs
+}
+
+/// `late`, `has-song` and the caption hashtags (without `#`)
+fn moment_keywords(moment: &crate::BerealMomentRecord) -> Vec<String> {
+    let mut keywords = vec![];
+    if moment.late {
+        keywords.push("late".to_owned());
+    }
+    if moment.song.is_some() {
+        keywords.push("has-song".to_owned());
+    }
+    if let Some(caption) = &moment.caption {
+        let hashtag = Regex::new(r"#(\w+)").expect("valid regex");
+        for tag in hashtag.captures_iter(caption) {
+            if !keywords.contains(&tag[1].to_owned()) {
+                keywords.push(tag[1].to_owned());
+            }
+        }
+    }
+    keywords
+}
+
 pub trait ExportJobGenerator {
     type ParamExportsT;
     type ParamFolderT;
 
     fn get_export_jobs(&self, inputs: &Self::ParamExportsT) -> Vec<ExportJobSpec>;
     fn get_output_folder(&self, inputs: &Self::ParamFolderT) -> PathBuf;
+    /// identifies the item in reports
+    fn describe(&self) -> String;
+    /// access and modification time of the exported files, `None` keeps the time of the export
+    fn file_time(&self, inputs: &Self::ParamExportsT) -> Option<SystemTime>;
 }
 
 pub struct ExportParameters {
-    pub input_path: PathBuf,
     pub image_format: ImageFormat,
+    pub encoding: EncodingOptions,
     pub desc_prefix: String,
     pub desc_suffix: String,
     pub disable_metadata: bool,
+    pub merge_metadata: bool,
+    pub strip_metadata: bool,
+    /// XMP sidecars next to the files with metadata
+    pub xmp_sidecars: bool,
+    /// file times set to the capture time
+    pub set_file_times: bool,
+    pub layout: Layout,
+}
+
+pub struct ExportSummary {
+    /// number of fully exported items
+    pub exported: usize,
+    /// number of files skipped as already exported by a previous run
+    pub up_to_date: usize,
+    /// items with at least one failed export job
+    pub failures: Vec<ItemReport>,
 }
 
 impl<'a> ExportJobGenerator for OutputMomentSpec<'a> {
@@ -69,6 +486,9 @@ impl<'a> ExportJobGenerator for OutputMomentSpec<'a> {
                 }
             }),
             time_taken: self.moment.naive_time_taken,
+            utc_offset: self.moment.utc_offset,
+            location: self.moment.location,
+            merge_existing: params.merge_metadata,
         };
 
         let meta = if params.disable_metadata {
@@ -77,33 +497,78 @@ impl<'a> ExportJobGenerator for OutputMomentSpec<'a> {
             metadata.into()
         };
 
-        let mut result = vec![
-            crate::ExportJobSpec::ImageConvert {
-                output_file_name: self.file_name_prefix.clone() + "_camera_front",
-                original_image_path: params.input_path.join(&self.moment.front_camera_path),
+        let mut result = match &params.layout {
+            Layout::Separate => vec![
+                crate::ExportJobSpec::ImageConvert {
+                    output_file_name: self.file_name("camera_front"),
+                    original_image_path: self.moment.front_camera_path.clone(),
+                    output_format: params.image_format.clone(),
+                    encoding: params.encoding.clone(),
+                    metadata: meta.clone(),
+                    strip_metadata: params.strip_metadata,
+                },
+                crate::ExportJobSpec::ImageConvert {
+                    output_file_name: self.file_name("camera_back"),
+                    original_image_path: self.moment.back_camera_path.clone(),
+                    output_format: params.image_format.clone(),
+                    encoding: params.encoding.clone(),
+                    metadata: meta.clone(),
+                    strip_metadata: params.strip_metadata,
+                },
+            ],
+            Layout::Inset(inset) => vec![crate::ExportJobSpec::Composite {
+                output_file_name: self.file_name("composite"),
+                back_image_path: self.moment.back_camera_path.clone(),
+                front_image_path: self.moment.front_camera_path.clone(),
                 output_format: params.image_format.clone(),
+                encoding: params.encoding.clone(),
                 metadata: meta.clone(),
-            },
-            crate::ExportJobSpec::ImageConvert {
-                output_file_name: self.file_name_prefix.clone() + "_camera_back",
-                original_image_path: params.input_path.join(&self.moment.back_camera_path),
+                inset: inset.clone(),
+            }],
+            Layout::Dual(options) => vec![crate::ExportJobSpec::DualCamera {
+                output_file_name: self.file_name(match options.direction {
+                    DualDirection::Horizontal => "side_by_side",
+                    DualDirection::Vertical => "stacked",
+                }),
+                back_image_path: self.moment.back_camera_path.clone(),
+                front_image_path: self.moment.front_camera_path.clone(),
                 output_format: params.image_format.clone(),
-                metadata: meta,
-            },
-        ];
+                encoding: params.encoding.clone(),
+                metadata: meta.clone(),
+                options: options.clone(),
+            }],
+        };
 
         if let Some(BerealBTSData::Video { path }) = &self.moment.behind_the_scenes {
             result.push(crate::ExportJobSpec::Copy {
-                output_file_name: self.file_name_prefix.clone() + "_BTS",
-                original_path: params.input_path.join(path),
+                output_file_name: self.file_name("BTS"),
+                original_path: path.clone(),
+                metadata: meta,
+                strip_metadata: params.strip_metadata,
             });
         }
+        if params.xmp_sidecars {
+            result = with_sidecars(result, moment_keywords(self.moment));
+        }
         result
     }
 
     fn get_output_folder(&self, output_folder_path: &PathBuf) -> PathBuf {
         output_folder_path.join(&self.folder)
     }
+
+    fn describe(&self) -> String {
+        self.folder
+            .join(&self.file_name_prefix)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn file_time(&self, params: &ExportParameters) -> Option<SystemTime> {
+        params
+            .set_file_times
+            .then(|| with_offset(self.moment.naive_time_taken, self.moment.utc_offset).into())
+    }
 }
 
 impl ExportJobGenerator for OutputRealmojiSpec {
@@ -112,17 +577,61 @@ impl ExportJobGenerator for OutputRealmojiSpec {
     type ParamFolderT = PathBuf;
 
     fn get_export_jobs(&self, params: &Self::ParamExportsT) -> Vec<crate::ExportJobSpec> {
-        vec![crate::ExportJobSpec::ImageConvert {
+        let description = format!(
+            "{}{} {}{}",
+            params.desc_prefix,
+            self.emoji,
+            if self.is_instant {
+                "instant realmoji"
+            } else {
+                "realmoji"
+            },
+            params.desc_suffix
+        );
+        let metadata = ImageMetadata {
+            caption: Some(description),
+            time_taken: self.creation_time,
+            utc_offset: self.utc_offset,
+            location: None,
+            merge_existing: params.merge_metadata,
+        };
+
+        let jobs = vec![crate::ExportJobSpec::ImageConvert {
             output_file_name: self.file_name_prefix.clone(),
-            original_image_path: params.input_path.join(&self.image_file),
+            original_image_path: self.image_file.clone(),
             output_format: params.image_format.clone(),
-            metadata: None,
-        }]
+            encoding: params.encoding.clone(),
+            metadata: if params.disable_metadata {
+                None
+            } else {
+                Some(metadata)
+            },
+            strip_metadata: params.strip_metadata,
+        }];
+        if params.xmp_sidecars {
+            let mut keywords = vec!["realmoji".to_owned()];
+            if self.is_instant {
+                keywords.push("instant".to_owned());
+            }
+            with_sidecars(jobs, keywords)
+        } else {
+            jobs
+        }
     }
 
     fn get_output_folder(&self, output_folder: &Self::ParamFolderT) -> PathBuf {
         output_folder.join(self.folder.clone())
     }
+
+    fn describe(&self) -> String {
+        self.image_file.to_string_lossy().to_string()
+    }
+
+    fn file_time(&self, params: &ExportParameters) -> Option<SystemTime> {
+        params
+            .set_file_times
+            .then(|| with_offset(self.creation_time, self.utc_offset).into())
+    }
 }
 
 fn calculate_chunk_size(paralelism_coeff: f32, work_items: usize) -> usize {
@@ -132,13 +641,73 @@ fn calculate_chunk_size(paralelism_coeff: f32, work_items: usize) -> usize {
     work_items.div_ceil(cpu_count)
 }
 
+/// resolves the collisions found during grouping, returns the items left out of the export
+pub fn apply_conflict_policy<T>(
+    specs: &mut Vec<T>,
+    collisions: &[Collision],
+    policy: ConflictPolicy,
+) -> Result<Vec<ItemReport>, BerealError>
+where
+    T: OutputName + ExportJobGenerator,
+{
+    let conflict = |collision: &Collision| BerealError::Conflict {
+        path: collision.output.clone(),
+        count: collision.count,
+    };
+    match policy {
+        ConflictPolicy::Overwrite => Ok(vec![]),
+        ConflictPolicy::Fail => match collisions.first() {
+            Some(collision) => Err(conflict(collision)),
+            None => Ok(vec![]),
+        },
+        ConflictPolicy::Rename => {
+            let mut taken: HashSet<PathBuf> = specs.iter().map(|s| s.output_name()).collect();
+            for collision in collisions {
+                let mut n = 2;
+                for &i in &collision.items[1..] {
+                    let name = specs[i].output_name();
+                    while taken.contains(&PathBuf::from(format!("{}_{n}", name.display()))) {
+                        n += 1;
+                    }
+                    specs[i].add_name_suffix(&format!("_{n}"));
+                    taken.insert(specs[i].output_name());
+                    n += 1;
+                }
+            }
+            Ok(vec![])
+        }
+        ConflictPolicy::Skip => {
+            let mut skipped = HashSet::new();
+            let mut reports = vec![];
+            for collision in collisions {
+                for &i in &collision.items[1..] {
+                    skipped.insert(i);
+                    reports.push(ItemReport {
+                        item: specs[i].describe(),
+                        status: ItemStatus::Skipped,
+                        errors: vec![conflict(collision)],
+                    });
+                }
+            }
+            let mut i = 0;
+            specs.retain(|_| {
+                i += 1;
+                !skipped.contains(&(i - 1))
+            });
+            Ok(reports)
+        }
+    }
+}
+
 pub fn export_generic<T, PathParam, ExportParam>(
+    input: &InputSource,
     path_params: PathParam,
     export_params: ExportParam,
     output_specs: &Vec<T>,
+    manifest: &Manifest,
     verbose: bool,
     paralelism_coeff: f32,
-) -> usize
+) -> ExportSummary
 where
     T: Sync + ExportJobGenerator<ParamExportsT = ExportParam, ParamFolderT = PathParam>,
     PathParam: Sync,
@@ -146,6 +715,17 @@ where
 {
     let total = AtomicUsize::new(0);
     let done = AtomicUsize::new(0);
+    let up_to_date = AtomicUsize::new(0);
+    let failures = Mutex::new(vec![]);
+
+    if output_specs.is_empty() {
+        // e.g. everything filtered out, there is nothing to split among the threads
+        return ExportSummary {
+            exported: 0,
+            up_to_date: 0,
+            failures: vec![],
+        };
+    }
 
     if verbose {
         println!("Spawning filesystem structure");
@@ -173,6 +753,8 @@ where
     let done = Arc::new(&done);
     let path_params = Arc::new(&path_params);
     let export_params = Arc::new(&export_params);
+    let failures = Arc::new(&failures);
+    let up_to_date = Arc::new(&up_to_date);
     thread::scope(|s| {
         for chunk in output_specs.chunks(chunk_size) {
             // acts as a "selective move"
@@ -181,32 +763,39 @@ where
             let done = Arc::clone(&done);
             let path_params = Arc::clone(&path_params);
             let export_params = Arc::clone(&export_params);
+            let failures = Arc::clone(&failures);
+            let up_to_date = Arc::clone(&up_to_date);
             s.spawn(move || {
                 for item in chunk {
                     let output_folder = item.get_output_folder(path_params.as_ref());
-                    let mut success = true;
-                    for job in item.get_export_jobs(export_params.as_ref()) {
-                        success &= match job {
-                            ExportJobSpec::ImageConvert {
-                                output_file_name,
-                                original_image_path,
-                                output_format,
-                                metadata,
-                            } => export_image(
-                                output_format,
-                                original_image_path,
-                                &output_folder,
-                                output_file_name,
-                                metadata,
-                            ),
-                            ExportJobSpec::Copy {
-                                output_file_name,
-                                original_path,
-                            } => perform_copy(original_path, &output_folder, output_file_name),
-                        };
+                    let jobs = item.get_export_jobs(export_params.as_ref());
+                    let job_count = jobs.len();
+                    let file_time = item.file_time(export_params.as_ref());
+                    let mut errors = vec![];
+                    for job in jobs {
+                        match run_job(input, job, &output_folder, manifest, file_time) {
+                            Ok(JobOutcome::UpToDate) => {
+                                up_to_date.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            }
+                            Ok(JobOutcome::Exported) => {}
+                            Err(e) => errors.push(e),
+                        }
                     }
-                    if success {
+                    if errors.is_empty() {
                         total.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    } else {
+                        let report = ItemReport {
+                            item: item.describe(),
+                            status: if errors.len() == job_count {
+                                ItemStatus::Skipped
+                            } else {
+                                ItemStatus::Partial
+                            },
+                            errors,
+                        };
+                        if let Ok(mut failures) = failures.lock() {
+                            failures.push(report);
+                        }
                     }
                 }
                 done.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -237,40 +826,436 @@ where
         });
     });
 
-    total.load(std::sync::atomic::Ordering::SeqCst)
+    ExportSummary {
+        exported: total.load(std::sync::atomic::Ordering::SeqCst),
+        up_to_date: up_to_date.load(std::sync::atomic::Ordering::SeqCst),
+        failures: failures
+            .lock()
+            .map(|mut f| std::mem::take(&mut *f))
+            .unwrap_or_default(),
+    }
 }
 
+/// the export without any side effects - lists the jobs `export_generic` would perform
+pub fn plan_generic<T, PathParam, ExportParam>(
+    input: &InputSource,
+    path_params: PathParam,
+    export_params: ExportParam,
+    output_specs: &[T],
+) -> Vec<PlannedJob>
+where
+    T: ExportJobGenerator<ParamExportsT = ExportParam, ParamFolderT = PathParam>,
+{
+    let mut plan = output_specs
+        .iter()
+        .flat_map(|item| {
+            let output_folder = item.get_output_folder(&path_params);
+            item.get_export_jobs(&export_params)
+                .into_iter()
+                .map(move |job| job.plan(input, &output_folder))
+        })
+        .collect::<Vec<_>>();
+    let destinations = plan.iter().map(|job| job.destination.clone()).counts();
+    for job in &mut plan {
+        job.conflict = destinations[&job.destination] > 1;
+    }
+    plan
+}
+
+pub fn print_plan(plan: &[PlannedJob], format: &PlanFormat) {
+    match format {
+        PlanFormat::Json => match serde_json::to_string_pretty(plan) {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Cannot serialize the export plan: {}", e),
+        },
+        PlanFormat::Table => {
+            let rows = plan
+                .iter()
+                .map(|job| {
+                    [
+                        job.action.to_string(),
+                        job.sources
+                            .iter()
+                            .map(|s| s.to_string_lossy().to_string())
+                            .collect::<Vec<_>>()
+                            .join(" + "),
+                        job.destination.to_string_lossy().to_string(),
+                        match (&job.format, &job.encoding) {
+                            (Some(format), Some(encoding)) => format!("{format} ({encoding})"),
+                            (Some(format), None) => format.clone(),
+                            _ => "-".to_owned(),
+                        },
+                        match &job.metadata {
+                            Some(metadata) => describe_metadata(metadata),
+                            None if job.strip_metadata => "stripped".to_owned(),
+                            None => "-".to_owned(),
+                        },
+                    ]
+                })
+                .collect::<Vec<_>>();
+            let header =
+                ["ACTION", "SOURCE", "DESTINATION", "FORMAT", "METADATA"].map(String::from);
+            let mut widths = header.clone().map(|h| h.chars().count());
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = max(*width, cell.chars().count());
+                }
+            }
+            for row in std::iter::once(&header).chain(rows.iter()) {
+                let line = row
+                    .iter()
+                    .zip(widths)
+                    .map(|(cell, width)| format!("{cell:width$}"))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                println!("{}", line.trim_end());
+            }
+            println!("{} files planned", plan.len());
+            let conflicts = plan.iter().filter(|job| job.conflict).count();
+            if conflicts > 0 {
+                println!("{} files written more than once (conflict)", conflicts);
+            }
+        }
+    }
+}
+
+fn describe_metadata(meta: &PlannedMetadata) -> String {
+    let mut parts = vec![format!("{} {}", meta.time_taken, meta.utc_offset)];
+    if let Some(location) = &meta.location {
+        parts.push(format!(
+            "gps {:.5},{:.5}",
+            location.latitude, location.longitude
+        ));
+    }
+    if let Some(caption) = &meta.caption {
+        parts.push(format!("\"{}\"", caption));
+    }
+    if meta.merge {
+        parts.push("merged".to_owned());
+    }
+    parts.join(", ")
+}
+
+enum JobOutcome {
+    Exported,
+    /// the manifest lists the same export from a previous run
+    UpToDate,
+}
+
+fn run_job(
+    input: &InputSource,
+    job: ExportJobSpec,
+    output_folder: &Path,
+    manifest: &Manifest,
+    file_time: Option<SystemTime>,
+) -> Result<JobOutcome, BerealError> {
+    let planned = job.plan(input, output_folder);
+    let with_file_time = |res| {
+        // files with failed metadata exist as well
+        if let Some(time) = file_time.filter(|_| planned.destination.is_file()) {
+            let source = planned.sources.first().unwrap_or(&planned.destination);
+            print_if_err(
+                set_file_time(&planned.destination, time),
+                source,
+                &planned.destination,
+            )?;
+        }
+        res
+    };
+    let mut contents = vec![];
+    for (source, display_path) in job.sources().into_iter().zip(&planned.sources) {
+        contents.push(print_if_err(
+            input.read(source),
+            display_path,
+            &planned.destination,
+        )?);
+    }
+    let entry = manifest.entry(
+        &planned.destination,
+        &job.sources(),
+        &contents,
+        planned.options(),
+    );
+    if manifest.is_up_to_date(&entry) {
+        return with_file_time(Ok(JobOutcome::UpToDate));
+    }
+    if planned.strip_metadata {
+        // nothing is written if the metadata cannot be removed
+        for (content, source) in contents.iter_mut().zip(&planned.sources) {
+            let stripped =
+                strip_metadata(content, source).map_err(|message| BerealError::Metadata {
+                    path: planned.destination.clone(),
+                    message,
+                });
+            *content = print_if_err(stripped, source, &planned.destination)?;
+        }
+    }
+
+    let res = match job {
+        ExportJobSpec::ImageConvert {
+            original_image_path,
+            output_format,
+            encoding,
+            metadata,
+            ..
+        } => export_image(
+            input,
+            contents.swap_remove(0),
+            output_format,
+            &encoding,
+            original_image_path,
+            &planned.destination,
+            metadata,
+        ),
+        ExportJobSpec::Copy {
+            original_path,
+            metadata,
+            ..
+        } => perform_copy(
+            input,
+            contents.swap_remove(0),
+            original_path,
+            &planned.destination,
+            metadata,
+        ),
+        ExportJobSpec::Composite {
+            metadata,
+            inset,
+            output_format,
+            encoding,
+            ..
+        } => {
+            let front = contents.pop().unwrap_or_default();
+            let back = contents.pop().unwrap_or_default();
+            export_composite(
+                (back, &planned.sources[0]),
+                (front, &planned.sources[1]),
+                &planned.destination,
+                &output_format,
+                &encoding,
+                metadata,
+                |back, front| compose_inset(back, front, &inset),
+            )
+        }
+        ExportJobSpec::DualCamera {
+            metadata,
+            options,
+            output_format,
+            encoding,
+            ..
+        } => {
+            let front = contents.pop().unwrap_or_default();
+            let back = contents.pop().unwrap_or_default();
+            export_composite(
+                (back, &planned.sources[0]),
+                (front, &planned.sources[1]),
+                &planned.destination,
+                &output_format,
+                &encoding,
+                metadata,
+                |back, front| compose_dual(back, front, &options),
+            )
+        }
+        ExportJobSpec::Sidecar {
+            related,
+            keywords,
+            metadata,
+            ..
+        } => {
+            let sidecar = xmp::Sidecar {
+                created: local_time(&metadata),
+                description: metadata.caption,
+                keywords,
+                location: metadata.location,
+                related,
+            };
+            let res = fs::write(&planned.destination, sidecar.to_xml())
+                .map_err(|e| BerealError::io(&planned.destination, e));
+            print_if_err(res, &planned.destination, &planned.destination)
+        }
+    };
+    with_file_time(res.map(|_| JobOutcome::Exported))?;
+    print_if_err(
+        manifest.record(entry),
+        planned.sources.first().unwrap_or(&planned.destination),
+        &planned.destination,
+    )?;
+    Ok(JobOutcome::Exported)
+}
+
+/// videos get the metadata, if the video cannot be edited, it is copied as-is and the error is returned
 fn perform_copy(
+    input: &InputSource,
+    content: Vec<u8>,
     original_path: PathBuf,
-    output_folder: &Path,
-    output_file_name_no_ext: String,
-) -> bool {
-    let mb_ext = original_path.extension().and_then(|s| s.to_str());
-    if let Some(ext) = mb_ext {
-        let target_path = &output_folder.join(output_file_name_no_ext + "." + ext);
-        let input_path = &original_path;
-        let res_bts = fs::copy(input_path, target_path);
-        print_if_err(&res_bts, input_path, target_path)
+    target_path: &Path,
+    metadata: Option<ImageMetadata>,
+) -> Result<(), BerealError> {
+    if original_path.extension().is_some() {
+        let input_path = &input.display_path(&original_path);
+        let (content, res_meta) = match metadata.filter(|_| is_video(&original_path)) {
+            Some(meta) => match mp4::set_metadata(&content, &meta.into()) {
+                Ok(edited) => (edited, Ok(())),
+                Err(message) => (
+                    content,
+                    Err(BerealError::Metadata {
+                        path: target_path.to_path_buf(),
+                        message,
+                    }),
+                ),
+            },
+            None => (content, Ok(())),
+        };
+        let res_bts = fs::write(target_path, content).map_err(|e| BerealError::io(target_path, e));
+        print_if_err(res_bts, input_path, target_path)?;
+        print_if_err(res_meta, input_path, target_path)
     } else {
         println!(
             "Warning, no extension detected! {}",
-            original_path.to_string_lossy()
+            input.display_path(&original_path).to_string_lossy()
         );
-        false
+        Err(BerealError::InvalidInput {
+            message: format!(
+                "no extension detected for {}",
+                input.display_path(&original_path).to_string_lossy()
+            ),
+        })
+    }
+}
+
+fn local_time(meta: &ImageMetadata) -> DateTime<FixedOffset> {
+    with_offset(meta.time_taken, meta.utc_offset)
+}
+
+fn with_offset(time: NaiveDateTime, offset: FixedOffset) -> DateTime<FixedOffset> {
+    time.and_local_timezone(offset)
+        .single()
+        .unwrap_or_else(|| time.and_utc().fixed_offset())
+}
+
+/// access and modification time
+fn set_file_time(path: &Path, time: SystemTime) -> Result<(), BerealError> {
+    let times = FileTimes::new().set_accessed(time).set_modified(time);
+    File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_times(times))
+        .map_err(|e| BerealError::io(path, e))
+}
+
+/// MP4 based videos, their metadata can be edited
+fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["mp4", "mov", "m4v"].contains(&e.to_lowercase().as_str()))
+}
+
+impl From<ImageMetadata> for mp4::VideoMetadata {
+    fn from(meta: ImageMetadata) -> Self {
+        mp4::VideoMetadata {
+            created: local_time(&meta),
+            description: meta.caption,
+            location: meta.location,
+        }
     }
 }
 
 fn export_image(
+    input: &InputSource,
+    content: Vec<u8>,
     output_format: ImageFormat,
+    encoding: &EncodingOptions,
     original_image_path: PathBuf,
-    output_folder: &Path,
-    output_file_name_no_ext: String,
+    target_path: &Path,
+    metadata: Option<ImageMetadata>,
+) -> Result<(), BerealError> {
+    let (_, lib_format) = conversion_format(&output_format, &original_image_path, encoding);
+    let input_path = &input.display_path(&original_image_path);
+    let base = existing_exif(&content, &metadata);
+    let res = if let Some(lib_format) = lib_format {
+        let exif = embedded_exif(lib_format, &metadata, base.clone());
+        convert_to(content, input_path, target_path, lib_format, encoding, exif)
+    } else {
+        fs::write(target_path, content).map_err(|e| BerealError::io(target_path, e))
+    };
+
+    print_if_err(res, input_path, target_path)?;
+    if lib_format.is_some_and(embeds_metadata) {
+        return Ok(());
+    }
+    print_if_err(
+        add_metadata(metadata, base, target_path).map_err(|message| BerealError::Metadata {
+            path: target_path.to_path_buf(),
+            message,
+        }),
+        input_path,
+        target_path,
+    )
+}
+
+/// images are given as the content and the path for messages,
+/// `compose` combines the decoded back and front images
+fn export_composite<F>(
+    back: (Vec<u8>, &Path),
+    front: (Vec<u8>, &Path),
+    target_path: &Path,
+    output_format: &ImageFormat,
+    encoding: &EncodingOptions,
     metadata: Option<ImageMetadata>,
-) -> bool {
-    let (image_extension, lib_format) = match output_format {
+    compose: F,
+) -> Result<(), BerealError>
+where
+    F: FnOnce(&RgbaImage, &RgbaImage) -> RgbaImage,
+{
+    let format = match composite_format(output_format, back.1) {
+        (_, Some(format)) => Ok(format),
+        (extension, None) => Err(BerealError::Encode {
+            path: target_path.to_path_buf(),
+            message: format!("unsupported image format {extension}"),
+        }),
+    };
+    let embedded = format.as_ref().is_ok_and(|format| embeds_metadata(*format));
+    // the composite keeps the metadata of the back image
+    let base = existing_exif(&back.0, &metadata);
+    let res = format.and_then(|format| {
+        let back_image = decode_image(back.0, back.1)?;
+        let front_image = decode_image(front.0, front.1)?;
+        let composite = compose(&back_image.to_rgba8(), &front_image.to_rgba8());
+        // the result is opaque, not every format supports alpha (JPEG)
+        write_image(
+            &DynamicImage::ImageRgba8(composite).to_rgb8().into(),
+            target_path,
+            format,
+            encoding,
+            embedded_exif(format, &metadata, base.clone()),
+        )
+    });
+
+    print_if_err(res, back.1, target_path)?;
+    if embedded {
+        return Ok(());
+    }
+    print_if_err(
+        add_metadata(metadata, base, target_path).map_err(|message| BerealError::Metadata {
+            path: target_path.to_path_buf(),
+            message,
+        }),
+        back.1,
+        target_path,
+    )
+}
+
+/// output file extension and the format to convert to (`None` - copy as-is)
+fn output_extension(
+    output_format: &ImageFormat,
+    original_image_path: &Path,
+) -> (String, Option<image::ImageFormat>) {
+    match output_format {
         ImageFormat::Jpeg => ("jpeg".to_owned(), Some(image::ImageFormat::Jpeg)),
         ImageFormat::Jpg => ("jpg".to_owned(), Some(image::ImageFormat::Jpeg)),
         ImageFormat::Png => ("png".to_owned(), Some(image::ImageFormat::Png)),
+        ImageFormat::Webp => ("webp".to_owned(), Some(image::ImageFormat::WebP)),
+        ImageFormat::Avif => ("avif".to_owned(), Some(image::ImageFormat::Avif)),
         ImageFormat::None => (
             original_image_path
                 .extension()
@@ -278,48 +1263,153 @@ fn export_image(
                 .unwrap_or("unknown".to_owned()),
             None,
         ),
-    };
+    }
+}
 
-    let target_path = &output_folder.join(output_file_name_no_ext + "." + &image_extension);
-    let input_path = &original_image_path;
-    let res = if let Some(lib_format) = lib_format {
-        convert_to(input_path, target_path, lib_format).map_err(|e| e.to_string())
+/// composites are always encoded, `ImageFormat::None` uses the format of the back image
+fn composite_format(
+    output_format: &ImageFormat,
+    back_image_path: &Path,
+) -> (String, Option<image::ImageFormat>) {
+    match output_extension(output_format, back_image_path) {
+        (extension, None) => {
+            let format = image::ImageFormat::from_extension(&extension);
+            (extension, format)
+        }
+        converted => converted,
+    }
+}
+
+/// originals are re-encoded in their own format when they have to be downscaled
+fn conversion_format(
+    output_format: &ImageFormat,
+    original_image_path: &Path,
+    encoding: &EncodingOptions,
+) -> (String, Option<image::ImageFormat>) {
+    if limits_size(encoding) {
+        composite_format(output_format, original_image_path)
     } else {
-        fs::copy(input_path, target_path)
-            .map_err(|e| e.to_string())
-            .map(|_| ())
+        output_extension(output_format, original_image_path)
+    }
+}
+
+fn limits_size(encoding: &EncodingOptions) -> bool {
+    encoding.max_width.is_some() || encoding.max_height.is_some()
+}
+
+/// human-readable encoder settings, `None` for formats without any
+fn encoder_settings(format: image::ImageFormat, encoding: &EncodingOptions) -> Option<String> {
+    let mut settings = match format {
+        image::ImageFormat::WebP if encoding.webp_lossless => vec!["lossless".to_owned()],
+        image::ImageFormat::WebP => vec![format!("quality {}", encoding.webp_quality)],
+        image::ImageFormat::Avif => vec![format!(
+            "quality {}, speed {}",
+            encoding.avif_quality, encoding.avif_speed
+        )],
+        image::ImageFormat::Jpeg => vec![format!("quality {}", encoding.jpeg_quality)],
+        image::ImageFormat::Png => vec![format!(
+            "compression {}",
+            value_name(&encoding.png_compression)
+        )],
+        _ => vec![],
     };
+    if let Some(width) = encoding.max_width {
+        settings.push(format!("max width {width}"));
+    }
+    if let Some(height) = encoding.max_height {
+        settings.push(format!("max height {height}"));
+    }
+    if limits_size(encoding) {
+        settings.push(format!(
+            "{} resampling",
+            value_name(&encoding.resize_filter)
+        ));
+    }
+    if settings.is_empty() {
+        None
+    } else {
+        Some(settings.join(", "))
+    }
+}
 
-    // this is utter crap, but im just adding the metadata export, not doing major refactors
-    // => TODO: use anyhow crate
-    print_if_err(&res, input_path, target_path)
-        && print_if_err(
-            &add_metadata(metadata, target_path),
-            input_path,
-            target_path,
-        )
+/// the command line name of an option value
+pub fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_owned())
+        .unwrap_or_default()
 }
 
-fn add_metadata(desired_meta: Option<ImageMetadata>, path: &Path) -> Result<(), String> {
-    use little_exif::exif_tag::ExifTag;
-    use little_exif::metadata::Metadata;
+/// img-parts cannot edit AVIF files, their metadata is embedded by the encoder instead of `add_metadata`
+fn embeds_metadata(format: image::ImageFormat) -> bool {
+    format == image::ImageFormat::Avif
+}
 
-    if desired_meta.is_none() {
-        return Ok(());
-    }
-    let meta = desired_meta.unwrap();
+/// encoded EXIF for formats which embed it while encoding
+fn embedded_exif(
+    format: image::ImageFormat,
+    metadata: &Option<ImageMetadata>,
+    base: Option<Metadata>,
+) -> Option<Vec<u8>> {
+    metadata
+        .as_ref()
+        .filter(|_| embeds_metadata(format))
+        .and_then(|meta| exif_metadata(meta.clone(), base).encode().ok())
+}
 
-    let mut metadata = Metadata::new();
+/// EXIF of the original image to merge the metadata into, `None` when not merging
+fn existing_exif(content: &[u8], metadata: &Option<ImageMetadata>) -> Option<Metadata> {
+    use little_exif::filetype::FileExtension;
 
-    if let Some(caption) = meta.caption {
-        if !caption.is_empty() {
-            metadata.set_tag(ExifTag::ImageDescription(caption));
-        }
+    if !metadata.as_ref().is_some_and(|m| m.merge_existing) {
+        return None;
     }
+    // old exports contain images with wrong extensions
+    let file_type = match image::guess_format(content).ok()? {
+        image::ImageFormat::Jpeg => FileExtension::JPEG,
+        image::ImageFormat::Png => FileExtension::PNG {
+            as_zTXt_chunk: false,
+        },
+        image::ImageFormat::WebP => FileExtension::WEBP,
+        image::ImageFormat::Tiff => FileExtension::TIFF,
+        _ => return None,
+    };
+    Metadata::new_from_vec(&content.to_vec(), file_type).ok()
+}
 
-    let time_tag =
-        ExifTag::DateTimeOriginal(meta.time_taken.format("%Y-%m-%d %H:%M:%S").to_string());
-    metadata.set_tag(time_tag);
+/// removes the EXIF (and XMP in JPEG) of images, the user data of MP4/MOV videos
+fn strip_metadata(content: &[u8], path: &Path) -> Result<Vec<u8>, String> {
+    if is_video(path) {
+        return mp4::strip_metadata(content);
+    }
+    let img = DynImage::from_bytes(Bytes::copy_from_slice(content))
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("unsupported image {path:?}"))?;
+    let img = match img {
+        DynImage::Jpeg(mut jpeg) => {
+            // APP1 holds EXIF and XMP, APP13 IPTC
+            jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP1);
+            jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP13);
+            DynImage::Jpeg(jpeg)
+        }
+        mut img => {
+            img.set_exif(None);
+            img
+        }
+    };
+    Ok(img.encoder().bytes().to_vec())
+}
+
+/// `base` - existing EXIF to update
+fn add_metadata(
+    desired_meta: Option<ImageMetadata>,
+    base: Option<Metadata>,
+    path: &Path,
+) -> Result<(), String> {
+    if desired_meta.is_none() {
+        return Ok(());
+    }
+    let metadata = exif_metadata(desired_meta.unwrap(), base);
 
     let img = fs::read(path).map_err(|e| e.to_string())?;
     let img = DynImage::from_bytes(Bytes::from(img)).map_err(|e| e.to_string())?;
@@ -346,30 +1436,488 @@ fn add_metadata(desired_meta: Option<ImageMetadata>, path: &Path) -> Result<(),
     }
 }
 
+/// `base` - existing EXIF, only the description, time and location tags are replaced
+fn exif_metadata(meta: ImageMetadata, base: Option<Metadata>) -> Metadata {
+    use little_exif::{exif_tag::ExifTag, ifd::ExifTagGroup};
+
+    let mut metadata = base.unwrap_or_else(Metadata::new);
+    if let Some(caption) = meta.caption {
+        if !caption.is_empty() {
+            metadata.set_tag(ExifTag::ImageDescription(caption));
+        }
+    }
+
+    let time_tag =
+        ExifTag::DateTimeOriginal(meta.time_taken.format("%Y-%m-%d %H:%M:%S").to_string());
+    metadata.set_tag(time_tag);
+    metadata.set_tag(ExifTag::OffsetTimeOriginal(meta.utc_offset.to_string()));
+
+    if let Some(location) = meta.location {
+        let lat_ref = if location.latitude < 0.0 { "S" } else { "N" };
+        let lon_ref = if location.longitude < 0.0 { "W" } else { "E" };
+        metadata.set_tag(ExifTag::GPSLatitudeRef(lat_ref.to_owned()));
+        metadata.set_tag(ExifTag::GPSLatitude(gps_coordinate(location.latitude)));
+        metadata.set_tag(ExifTag::GPSLongitudeRef(lon_ref.to_owned()));
+        metadata.set_tag(ExifTag::GPSLongitude(gps_coordinate(location.longitude)));
+    } else if let Some(gps) = metadata.get_ifd(ExifTagGroup::GPS, 0) {
+        // merged, the location of the original must not stay (e.g. --no-location)
+        let tags = gps.get_tags().clone();
+        let gps = metadata.get_ifd_mut(ExifTagGroup::GPS, 0);
+        tags.into_iter().for_each(|tag| gps.remove_tag(tag));
+    }
+    metadata
+}
+
+/// degrees, minutes and seconds (EXIF format), the sign is stored separately in the reference tag
+fn gps_coordinate(value: f64) -> Vec<little_exif::rational::uR64> {
+    use little_exif::rational::uR64;
+
+    const SECONDS_PRECISION: u32 = 10000;
+    // rounded as a whole, so that e.g. 59.99999" carries over into the minutes
+    let total = (value.abs() * 3600.0 * SECONDS_PRECISION as f64).round() as u64;
+    let minute = 60 * SECONDS_PRECISION as u64;
+    let degrees = total / (60 * minute);
+    let minutes = total / minute % 60;
+    let seconds = total % minute;
+    vec![
+        uR64 {
+            nominator: degrees as u32,
+            denominator: 1,
+        },
+        uR64 {
+            nominator: minutes as u32,
+            denominator: 1,
+        },
+        uR64 {
+            nominator: seconds as u32,
+            denominator: SECONDS_PRECISION,
+        },
+    ]
+}
+
 fn convert_to(
-    from: &PathBuf,
-    to: &PathBuf,
+    from: Vec<u8>,
+    from_path: &Path,
+    to: &Path,
+    format: image::ImageFormat,
+    encoding: &EncodingOptions,
+    exif: Option<Vec<u8>>,
+) -> Result<(), BerealError> {
+    write_image(&decode_image(from, from_path)?, to, format, encoding, exif)
+}
+
+fn decode_image(from: Vec<u8>, from_path: &Path) -> Result<DynamicImage, BerealError> {
+    ImageReader::new(Cursor::new(from))
+        .with_guessed_format()
+        .map_err(|e| BerealError::io(from_path, e))?
+        .decode()
+        .map_err(|e| BerealError::Decode {
+            path: from_path.to_path_buf(),
+            message: e.to_string(),
+        })
+}
+
+fn write_image(
+    img: &DynamicImage,
+    to: &Path,
     format: image::ImageFormat,
-) -> Result<(), image::ImageError> {
-    let file = File::create(absolute(to)?)?;
+    encoding: &EncodingOptions,
+    exif: Option<Vec<u8>>,
+) -> Result<(), BerealError> {
+    let img = &*fit_to_max_size(img, encoding);
+    if format == image::ImageFormat::WebP {
+        // the image crate only encodes lossless WebP
+        let encoded = encode_webp(img, encoding).map_err(|message| BerealError::Encode {
+            path: to.to_path_buf(),
+            message,
+        })?;
+        return fs::write(to, &*encoded).map_err(|e| BerealError::io(to, e));
+    }
+    if format == image::ImageFormat::Avif {
+        let encoded = encode_avif(img, encoding, exif).map_err(|message| BerealError::Encode {
+            path: to.to_path_buf(),
+            message,
+        })?;
+        return fs::write(to, encoded).map_err(|e| BerealError::io(to, e));
+    }
+    let file = absolute(to)
+        .and_then(File::create)
+        .map_err(|e| BerealError::io(to, e))?;
     let mut target = BufWriter::new(file);
-    let img = ImageReader::open(canonicalize(from)?)?
-        .with_guessed_format()?
-        .decode()?;
-    img.write_to(&mut target, format)
-}
-
-/// returns false on error
-fn print_if_err<R, E: Display>(res: &Result<R, E>, from: &Path, to: &Path) -> bool {
-    res.as_ref().map_or_else(
-        |e| {
-            println!(
-                "{} -> {} failed: {e}",
-                from.to_string_lossy(),
-                to.to_string_lossy()
-            );
-            false
+    let res = match format {
+        image::ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut target, encoding.jpeg_quality);
+            if img.color().has_alpha() {
+                // JPEG has no alpha channel
+                DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)
+            } else {
+                img.write_with_encoder(encoder)
+            }
+        }
+        image::ImageFormat::Png => {
+            let compression = match encoding.png_compression {
+                PngCompression::Fast => CompressionType::Fast,
+                PngCompression::Default => CompressionType::Default,
+                PngCompression::Best => CompressionType::Best,
+            };
+            img.write_with_encoder(PngEncoder::new_with_quality(
+                &mut target,
+                compression,
+                PngFilterType::Adaptive,
+            ))
+        }
+        format => img.write_to(&mut target, format),
+    };
+    res.map_err(|e| match e {
+        image::ImageError::IoError(e) => BerealError::io(to, e),
+        e => BerealError::Encode {
+            path: to.to_path_buf(),
+            message: e.to_string(),
         },
-        |_| true,
-    )
+    })
+}
+
+/// downscales the image to fit the maximum dimensions, smaller images are kept
+fn fit_to_max_size<'a>(img: &'a DynamicImage, encoding: &EncodingOptions) -> Cow<'a, DynamicImage> {
+    let max_width = encoding.max_width.unwrap_or(u32::MAX);
+    let max_height = encoding.max_height.unwrap_or(u32::MAX);
+    if img.width() <= max_width && img.height() <= max_height {
+        return Cow::Borrowed(img);
+    }
+    let filter = match encoding.resize_filter {
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Gaussian => FilterType::Gaussian,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    };
+    Cow::Owned(img.resize(max_width, max_height, filter))
+}
+
+fn encode_webp(img: &DynamicImage, encoding: &EncodingOptions) -> Result<webp::WebPMemory, String> {
+    let (width, height) = (img.width(), img.height());
+    let (pixels, encoder);
+    if img.color().has_alpha() {
+        pixels = img.to_rgba8().into_raw();
+        encoder = webp::Encoder::from_rgba(&pixels, width, height);
+    } else {
+        pixels = img.to_rgb8().into_raw();
+        encoder = webp::Encoder::from_rgb(&pixels, width, height);
+    }
+    // encode/encode_lossless panic on libwebp errors, e.g. images wider than 16383 pixels
+    encoder
+        .encode_simple(encoding.webp_lossless, encoding.webp_quality as f32)
+        .map_err(|e| format!("{e:?}"))
+}
+
+/// `exif` - raw TIFF EXIF block
+fn encode_avif(
+    img: &DynamicImage,
+    encoding: &EncodingOptions,
+    exif: Option<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let mut encoder = ravif::Encoder::new()
+        .with_quality(encoding.avif_quality as f32)
+        .with_speed(encoding.avif_speed);
+    if let Some(exif) = exif {
+        encoder = encoder.with_exif(exif);
+    }
+    let rgba = img.to_rgba8();
+    let pixels = rgba
+        .pixels()
+        .map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3]))
+        .collect::<Vec<_>>();
+    encoder
+        .encode_rgba(ravif::Img::new(
+            &pixels[..],
+            rgba.width() as usize,
+            rgba.height() as usize,
+        ))
+        .map(|encoded| encoded.avif_file)
+        .map_err(|e| e.to_string())
+}
+
+/// prints the error (if any) and passes the result through
+fn print_if_err<R>(res: Result<R, BerealError>, from: &Path, to: &Path) -> Result<R, BerealError> {
+    if let Err(e) = &res {
+        println!(
+            "{} -> {} failed: {e}",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        );
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{group::find_collisions, test_dir::TestDir};
+    use chrono::{Offset, Utc};
+
+    fn realmoji(folder: &str, prefix: &str) -> OutputRealmojiSpec {
+        OutputRealmojiSpec {
+            folder: PathBuf::from(folder),
+            file_name_prefix: prefix.to_owned(),
+            image_file: PathBuf::from(format!("Photos/realmoji/{prefix}.jpg")),
+            emoji: "😃".to_owned(),
+            is_instant: false,
+            creation_time: NaiveDateTime::default(),
+            utc_offset: Utc.fix(),
+        }
+    }
+
+    #[test]
+    fn renames_colliding_items() {
+        let mut specs = vec![
+            realmoji("a", "x"),
+            realmoji("a", "x_2"),
+            realmoji("b", "x"),
+            realmoji("a", "x"),
+            realmoji("a", "x"),
+        ];
+        let collisions = find_collisions(&specs);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].items, vec![0, 3, 4]);
+
+        apply_conflict_policy(&mut specs, &collisions, ConflictPolicy::Rename).unwrap();
+        let names = specs
+            .iter()
+            .map(|s| s.file_name_prefix.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["x", "x_2", "x", "x_3", "x_4"]);
+
+        let mut specs = vec![realmoji("a", "x"), realmoji("a", "x")];
+        let collisions = find_collisions(&specs);
+        let skipped = apply_conflict_policy(&mut specs, &collisions, ConflictPolicy::Skip).unwrap();
+        assert_eq!((specs.len(), skipped.len()), (1, 1));
+        assert!(apply_conflict_policy(&mut specs, &collisions, ConflictPolicy::Fail).is_err());
+    }
+
+    fn export_parameters(image_format: ImageFormat) -> ExportParameters {
+        ExportParameters {
+            image_format,
+            encoding: default_encoding(),
+            desc_prefix: String::new(),
+            desc_suffix: String::new(),
+            disable_metadata: false,
+            merge_metadata: false,
+            strip_metadata: false,
+            xmp_sidecars: false,
+            set_file_times: false,
+            layout: Layout::Separate,
+        }
+    }
+
+    #[test]
+    fn plans_without_writing() {
+        let dir = TestDir::new("plan");
+        let output = dir.join("out");
+        let input = InputSource::open(&dir).unwrap();
+        let specs = vec![realmoji("a", "x"), realmoji("b/c", "y")];
+
+        let plan = plan_generic(
+            &input,
+            output.clone(),
+            export_parameters(ImageFormat::Png),
+            &specs,
+        );
+        let destinations = plan.iter().map(|job| &job.destination).collect::<Vec<_>>();
+        assert_eq!(
+            destinations,
+            vec![&output.join("a/x.png"), &output.join("b/c/y.png")]
+        );
+        assert!(plan.iter().all(|job| job.action == "convert"
+            && job.format.as_deref() == Some("png")
+            && job.encoding.as_deref() == Some("compression default")));
+
+        let plan = plan_generic(
+            &input,
+            output.clone(),
+            export_parameters(ImageFormat::None),
+            &specs,
+        );
+        assert_eq!(plan[0].action, "copy");
+        assert_eq!(plan[0].destination, output.join("a/x.jpg"));
+        assert_eq!(plan[0].format, None);
+
+        print_plan(&plan, &PlanFormat::Table);
+        print_plan(&plan, &PlanFormat::Json);
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn handles_all_colliding_items() {
+        let dir = TestDir::new("collide");
+        let output = dir.join("out");
+        let input = InputSource::open(&dir).unwrap();
+        let mut specs = vec![realmoji("", "x"), realmoji("", "x"), realmoji("", "x")];
+
+        // a dry run with --on-conflict fail plans the colliding files
+        let plan = plan_generic(
+            &input,
+            output.clone(),
+            export_parameters(ImageFormat::None),
+            &specs,
+        );
+        assert_eq!(plan.len(), 3);
+        assert!(plan.iter().all(|job| job.conflict));
+
+        let collisions = find_collisions(&specs);
+        let skipped = apply_conflict_policy(&mut specs, &collisions, ConflictPolicy::Skip).unwrap();
+        assert_eq!((specs.len(), skipped.len()), (1, 2));
+
+        let manifest = Manifest::open(&output, true).unwrap();
+        let summary = export_generic(
+            &input,
+            output.clone(),
+            export_parameters(ImageFormat::None),
+            &Vec::<OutputRealmojiSpec>::new(),
+            &manifest,
+            false,
+            1.0,
+        );
+        assert_eq!(summary.exported, 0);
+    }
+
+    #[test]
+    fn gps_coordinate_to_degrees_minutes_seconds() {
+        // 50.0755° = 50° 4' 31.8"
+        let dms = gps_coordinate(-50.0755);
+        assert_eq!(dms[0].nominator, 50);
+        assert_eq!(dms[1].nominator, 4);
+        assert_eq!(dms[2].nominator, 318000);
+        assert_eq!(dms[2].denominator, 10000);
+
+        // rounds up to the next minute (and degree), not to 59' 60"
+        let dms = gps_coordinate(0.99999999);
+        let dms = dms.iter().map(|r| r.nominator).collect::<Vec<_>>();
+        assert_eq!(dms, vec![1, 0, 0]);
+        let dms = gps_coordinate(14.0 + 25.0 / 60.0 - 1e-9);
+        let dms = dms.iter().map(|r| r.nominator).collect::<Vec<_>>();
+        assert_eq!(dms, vec![14, 25, 0]);
+    }
+
+    fn default_encoding() -> EncodingOptions {
+        EncodingOptions {
+            webp_lossless: false,
+            webp_quality: 75,
+            avif_quality: 75,
+            avif_speed: 6,
+            jpeg_quality: 75,
+            png_compression: PngCompression::Default,
+            max_width: None,
+            max_height: None,
+            resize_filter: ResizeFilter::Lanczos3,
+        }
+    }
+
+    #[test]
+    fn webp_export_keeps_exif_and_reports_encoder_errors() {
+        let dir = TestDir::new("webp");
+        let target = dir.join("a.webp");
+        let img = DynamicImage::new_rgb8(40, 30);
+        write_image(
+            &img,
+            &target,
+            image::ImageFormat::WebP,
+            &default_encoding(),
+            None,
+        )
+        .unwrap();
+        let metadata = ImageMetadata {
+            caption: Some("caption".to_owned()),
+            time_taken: NaiveDateTime::default(),
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            location: None,
+            merge_existing: false,
+        };
+        add_metadata(Some(metadata), None, &target).unwrap();
+
+        let content = fs::read(&target).unwrap();
+        let decoded = image::load_from_memory(&content).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (40, 30));
+        let exif = DynImage::from_bytes(Bytes::from(content))
+            .unwrap()
+            .and_then(|img| img.exif());
+        assert!(exif.is_some());
+
+        // over the WebP size limit, the item fails instead of the whole export
+        let too_wide = DynamicImage::new_rgb8(16384, 1);
+        let res = write_image(
+            &too_wide,
+            &dir.join("b.webp"),
+            image::ImageFormat::WebP,
+            &default_encoding(),
+            None,
+        );
+        assert!(matches!(res, Err(BerealError::Encode { .. })));
+    }
+
+    #[test]
+    fn downscales_only_larger_images() {
+        let encoding = EncodingOptions {
+            max_width: Some(500),
+            resize_filter: ResizeFilter::Triangle,
+            ..default_encoding()
+        };
+        let large = DynamicImage::new_rgb8(1500, 2000);
+        let resized = fit_to_max_size(&large, &encoding);
+        assert_eq!((resized.width(), resized.height()), (500, 667));
+
+        let small = DynamicImage::new_rgb8(300, 2000);
+        assert!(matches!(
+            fit_to_max_size(&small, &encoding),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            encoder_settings(image::ImageFormat::Jpeg, &encoding).unwrap(),
+            "quality 75, max width 500, triangle resampling"
+        );
+    }
+
+    #[test]
+    fn merges_into_existing_exif() {
+        use little_exif::{exif_tag::ExifTag, filetype::FileExtension};
+
+        let mut original = vec![];
+        DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut Cursor::new(&mut original), image::ImageFormat::Jpeg)
+            .unwrap();
+        let mut exif = Metadata::new();
+        exif.set_tag(ExifTag::Orientation(vec![6]));
+        exif.set_tag(ExifTag::ImageDescription("old".to_owned()));
+        exif.set_tag(ExifTag::GPSLatitudeRef("N".to_owned()));
+        exif.write_to_vec(&mut original, FileExtension::JPEG)
+            .unwrap();
+
+        let metadata = Some(ImageMetadata {
+            caption: Some("new".to_owned()),
+            time_taken: NaiveDateTime::default(),
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            location: None,
+            merge_existing: true,
+        });
+        let merged = exif_metadata(
+            metadata.clone().unwrap(),
+            existing_exif(&original, &metadata),
+        );
+        assert!(merged
+            .get_tag(&ExifTag::Orientation(vec![]))
+            .any(|tag| matches!(tag, ExifTag::Orientation(v) if v == &vec![6])));
+        assert!(merged
+            .get_tag(&ExifTag::ImageDescription(String::new()))
+            .any(|tag| matches!(tag, ExifTag::ImageDescription(v) if v == "new")));
+        // no location to keep
+        assert_eq!(
+            merged
+                .get_tag(&ExifTag::GPSLatitudeRef(String::new()))
+                .count(),
+            0
+        );
+
+        let stripped = strip_metadata(&original, Path::new("a.jpg")).unwrap();
+        assert!(existing_exif(&stripped, &metadata)
+            .is_none_or(|exif| exif.get_tag(&ExifTag::Orientation(vec![])).count() == 0));
+    }
 }
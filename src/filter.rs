@@ -1,17 +1,19 @@
 use itertools::Itertools;
 
-use crate::{args::TimeInterval, parser::BerealMomentRecord};
+use crate::{args::TimeInterval, error::BerealError, parser::BerealMomentRecord};
 
 pub fn filter_moments(
     moments: Vec<BerealMomentRecord>,
     caption_regex: Option<String>,
     intervals_allowed: Vec<TimeInterval>,
-) -> Result<Vec<BerealMomentRecord>, String> {
+) -> Result<Vec<BerealMomentRecord>, BerealError> {
     let mut result: Vec<BerealMomentRecord> = vec![];
     let mut regex = None;
     if let Some(rexp) = caption_regex {
         let re =
-            regex::Regex::new(&rexp.to_lowercase()).map_err(|e| format!("invalid regex: {}", e))?;
+            regex::Regex::new(&rexp.to_lowercase()).map_err(|e| BerealError::InvalidArgument {
+                message: format!("invalid regex: {}", e),
+            })?;
         regex = Some(re);
     }
 
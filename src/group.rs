@@ -1,17 +1,20 @@
 use std::{
+    cmp::max,
     collections::{HashMap, HashSet},
-    hash::Hash,
     path::PathBuf,
     vec,
 };
 
-use chrono::{Datelike, Timelike};
+use chrono::{Datelike, FixedOffset, Month, NaiveDateTime, Timelike, Weekday};
 use itertools::Itertools;
-use unic_ucd_name::Name;
+use serde::Serialize;
 
 use crate::{
-    args::{MemoriesGrouping, RealmojiGrouping},
-    parser::BerealMomentRecord,
+    args::{EmojiVariants, MemoriesGrouping, RealmojiGrouping},
+    emoji,
+    error::BerealError,
+    parser::{BerealMomentRecord, BerealRealmojiSelectionRecord, BerealSongData},
+    template::{Template, TemplateValues, Value, CAMERA_PLACEHOLDER},
     BerealRealmojiRecord,
 };
 
@@ -24,6 +27,63 @@ pub struct OutputMomentSpec<'a> {
     pub moment: &'a BerealMomentRecord,
 }
 
+impl OutputMomentSpec<'_> {
+    /// name of an exported file (without the extension), `part` identifies the file, e.g. `camera_front`
+    pub fn file_name(&self, part: &str) -> String {
+        if self.file_name_prefix.contains(CAMERA_PLACEHOLDER) {
+            self.file_name_prefix.replace(CAMERA_PLACEHOLDER, part)
+        } else {
+            format!("{}_{part}", self.file_name_prefix)
+        }
+    }
+}
+
+/// output items identified by their folder and file name prefix
+pub trait OutputName {
+    /// folder and file name prefix, relative to the output directory
+    fn output_name(&self) -> PathBuf;
+    fn add_name_suffix(&mut self, suffix: &str);
+}
+
+impl OutputName for OutputMomentSpec<'_> {
+    fn output_name(&self) -> PathBuf {
+        self.folder.join(&self.file_name_prefix)
+    }
+
+    fn add_name_suffix(&mut self, suffix: &str) {
+        self.file_name_prefix += suffix;
+    }
+}
+
+/// items whose files would overwrite each other
+#[derive(Debug, Clone, Serialize)]
+pub struct Collision {
+    /// folder and file name prefix, relative to the output directory
+    pub output: PathBuf,
+    /// indices of the colliding items, in export order
+    #[serde(skip)]
+    pub items: Vec<usize>,
+    pub count: usize,
+}
+
+/// finds items sharing the output name, e.g. moments taken within the same second
+pub fn find_collisions<T: OutputName>(specs: &[T]) -> Vec<Collision> {
+    let mut outputs: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (i, spec) in specs.iter().enumerate() {
+        outputs.entry(spec.output_name()).or_default().push(i);
+    }
+    outputs
+        .into_iter()
+        .filter(|(_, items)| items.len() > 1)
+        .map(|(output, items)| Collision {
+            output,
+            count: items.len(),
+            items,
+        })
+        .sorted_by_key(|c| c.items[0])
+        .collect()
+}
+
 fn translate_bereal_moment_to_name_prefix(moment: &BerealMomentRecord) -> String {
     let time = moment.naive_time_taken;
     format!(
@@ -37,12 +97,74 @@ fn translate_bereal_moment_to_name_prefix(moment: &BerealMomentRecord) -> String
     )
 }
 
-pub fn group_moments(
-    moments: &mut Vec<BerealMomentRecord>,
-    grouping: MemoriesGrouping,
-) -> Result<Vec<OutputMomentSpec<'_>>, String> {
+/// date and time placeholders shared by all templates
+fn time_value(time: &NaiveDateTime, name: &str) -> Value {
+    match name {
+        "year" => Value::Number(time.year().into()),
+        "month" => Value::Number(time.month().into()),
+        "day" => Value::Number(time.day().into()),
+        "hour" => Value::Number(time.hour().into()),
+        "minute" => Value::Number(time.minute().into()),
+        "second" => Value::Number(time.second().into()),
+        "month_name" => Value::Text(
+            Month::try_from(time.month() as u8)
+                .map(|m| m.name().to_owned())
+                .unwrap_or_default(),
+        ),
+        "week" => Value::Number(time.iso_week().week().into()),
+        "week_year" => Value::Number(time.iso_week().year().into()),
+        "weekday" => Value::Text(weekday_name(time.weekday()).to_owned()),
+        "date" => Value::Text(time.format("%Y-%m-%d").to_string()),
+        "time" => Value::Text(time.format("%H-%M-%S").to_string()),
+        _ => Value::Flag(false),
+    }
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+impl TemplateValues for BerealMomentRecord {
+    fn value(&self, name: &str) -> Value {
+        match name {
+            "late" => Value::Flag(self.late),
+            "bts" => Value::Flag(self.behind_the_scenes.is_some()),
+            "song" => Value::Flag(self.song.is_some()),
+            _ => time_value(&self.naive_time_taken, name),
+        }
+    }
+}
+
+/// `template` replaces both the `groupings` and the default file names
+pub fn group_moments<'a>(
+    moments: &'a mut Vec<BerealMomentRecord>,
+    groupings: &[MemoriesGrouping],
+    template: Option<&Template>,
+) -> Result<Vec<OutputMomentSpec<'a>>, BerealError> {
     let mut result = vec![];
 
+    if let Some(template) = template {
+        return Ok(moments
+            .iter()
+            .map(|moment| {
+                let (folder, file_name_prefix) = template.render(moment);
+                OutputMomentSpec {
+                    folder,
+                    file_name_prefix,
+                    moment,
+                }
+            })
+            .collect());
+    }
+
     for moment in moments {
         let prefix = translate_bereal_moment_to_name_prefix(moment);
 
@@ -53,38 +175,70 @@ pub fn group_moments(
         })
     }
 
-    match grouping {
-        MemoriesGrouping::None => Ok(result),
-        // I could be done in a single for loop conditionally nesting if needed
-        // however whenever i use the group from (key, group), I move out of it
-        // and grouping in another level (year->month) becomes impossible
-
-        // I might be missing something but I think it should be possible to
-        // re-evaluate the group
-
-        // the way grouping works now is by appending a folder name after each group_* pass
-        // in the Grouping::Day scnario, we first append year folder, then month folder
-        // (creating month subfolders in year folders) and then day folders
-        MemoriesGrouping::Year => {
-            group_year(&mut result);
-            Ok(result)
-        }
-        MemoriesGrouping::Month => {
-            group_year(&mut result);
-            group_month(&mut result);
-            Ok(result)
-        }
-        MemoriesGrouping::Day => {
-            group_year(&mut result);
-            group_month(&mut result);
-            group_day(&mut result, false);
-            Ok(result)
+    // I could be done in a single for loop conditionally nesting if needed
+    // however whenever i use the group from (key, group), I move out of it
+    // and grouping in another level (year->month) becomes impossible
+
+    // I might be missing something but I think it should be possible to
+    // re-evaluate the group
+
+    // the way grouping works now is by appending a folder name after each group_* pass
+    // in the Grouping::Day scnario, we first append year folder, then month folder
+    // (creating month subfolders in year folders) and then day folders.
+    // Combined groupings (e.g. year,late) nest in the given order the same way
+    // year, month and day levels already in the chain are not repeated (year,month => year/month)
+    let mut date_depth = 0;
+    for grouping in groupings {
+        match grouping {
+            MemoriesGrouping::None => {}
+            MemoriesGrouping::Year => group_date_levels(&mut result, &mut date_depth, 1),
+            MemoriesGrouping::Month => group_date_levels(&mut result, &mut date_depth, 2),
+            MemoriesGrouping::Day => group_date_levels(&mut result, &mut date_depth, 3),
+            MemoriesGrouping::DayFlat => group_day(&mut result, true),
+            MemoriesGrouping::Week => group_week(&mut result),
+            MemoriesGrouping::Weekday => group_weekday(&mut result),
+            MemoriesGrouping::Late => group_attribute(&mut result, |m| {
+                if m.late { "late" } else { "on-time" }.to_owned()
+            }),
+            MemoriesGrouping::Bts => group_attribute(&mut result, |m| {
+                if m.behind_the_scenes.is_some() {
+                    "with-bts"
+                } else {
+                    "without-bts"
+                }
+                .to_owned()
+            }),
+            MemoriesGrouping::Song => group_attribute(&mut result, |m| match &m.song {
+                Some(BerealSongData::Spotify { spotify_song_id }) => {
+                    format!("spotify-{}", spotify_song_id)
+                }
+                None => "no-song".to_owned(),
+            }),
         }
-        MemoriesGrouping::DayFlat => {
-            group_day(&mut result, true);
-            Ok(result)
+    }
+    Ok(result)
+}
+
+/// adds the year, month and day levels from `depth` down to `target` (1 - year, 2 - month, 3 - day)
+fn group_date_levels(result: &mut [OutputMomentSpec<'_>], depth: &mut usize, target: usize) {
+    for level in *depth..target {
+        match level {
+            0 => group_year(result),
+            1 => group_month(result),
+            _ => group_day(result, false),
         }
     }
+    *depth = max(*depth, target);
+}
+
+/// attributes are not ordered like the times, every moment gets its folder
+fn group_attribute<F>(result: &mut [OutputMomentSpec<'_>], folder_name: F)
+where
+    F: Fn(&BerealMomentRecord) -> String,
+{
+    for moment in result {
+        moment.folder = moment.folder.join(folder_name(moment.moment));
+    }
 }
 
 fn group_year(result: &mut [OutputMomentSpec<'_>]) {
@@ -135,6 +289,35 @@ fn group_day(result: &mut [OutputMomentSpec<'_>], flat: bool) {
     }
 }
 
+/// ISO weeks, the year is the week-based one (e.g. 2024-12-30 is in 2025/W01)
+fn group_week(result: &mut [OutputMomentSpec<'_>]) {
+    let year_group = result
+        .iter_mut()
+        .chunk_by(|v| v.moment.naive_time_taken.iso_week().year());
+    for (y_key, group) in &year_group {
+        let week_group = group.chunk_by(|v| v.moment.naive_time_taken.iso_week().week());
+        for (w_key, w_group) in &week_group {
+            for moment in w_group {
+                moment.folder = moment
+                    .folder
+                    .join(format!("{:04}", y_key))
+                    .join(format!("W{:02}", w_key));
+            }
+        }
+    }
+}
+
+fn group_weekday(result: &mut [OutputMomentSpec<'_>]) {
+    let weekday_group = result
+        .iter_mut()
+        .chunk_by(|v| v.moment.naive_time_taken.weekday());
+    for (key, group) in &weekday_group {
+        for moment in group {
+            moment.folder = moment.folder.join(weekday_name(key));
+        }
+    }
+}
+
 /// represents the paths where
 /// outputs will be saved, relative to the output directory
 #[derive(Debug)]
@@ -142,104 +325,354 @@ pub struct OutputRealmojiSpec {
     pub folder: PathBuf,
     pub file_name_prefix: String,
     pub image_file: PathBuf,
-    // TODO: metadata propagation relies on correct extraction and parsin of te realmojis json file
-    // - it seems there are 2 json files realmojis.json being concatenated into one
-    //      - lets call them realmoji-post and realmoji-emoji json file
-    //     - realmoji-post file contains all "posts" of realmojis while realmoji-emoji file contains the "selection" history (of the 4-5 emoji variants)
-    //
-    // - realmojis here rely on the realmoji-post file because it contains all realmojis, even the "flash" ones
-    // - creation time of "flash" realmojis corresponds to their post time HOWEVER creation time of the other realmojis (in realmoji-emoji json file) have to be inferred from the realmoji-emoji file (post time does not correspond to creation time)
-    // hence to add metadata to the realmoji output, we need more sophisticated parsing
-    // TODO (above note, parsing realmoji creation time)
-    // simply parse realmoji-emoji file, create a map, query realmoji input paths
-    // -> mapped?     -> use creation time from map
-    // -> not mapped? -> flash realmoji => use post time as creation time
+    pub emoji: String,
+    pub is_instant: bool,
+    /// creation time of the realmoji image (not necessarily the time it was posted)
+    pub creation_time: NaiveDateTime,
+    pub utc_offset: FixedOffset,
 }
 
+impl OutputName for OutputRealmojiSpec {
+    fn output_name(&self) -> PathBuf {
+        self.folder.join(&self.file_name_prefix)
+    }
+
+    fn add_name_suffix(&mut self, suffix: &str) {
+        self.file_name_prefix += suffix;
+    }
+}
+
+/// The realmoji post history contains all realmojis (even the instant ones) but
+/// the post time corresponds to the creation time only for the instant ones.
+/// Other realmojis are created in advance - their creation time is taken from the selection history.
+struct RealmojiCreationTimes {
+    selected: HashMap<PathBuf, (NaiveDateTime, FixedOffset)>,
+}
+
+impl RealmojiCreationTimes {
+    fn new(selections: &[BerealRealmojiSelectionRecord]) -> RealmojiCreationTimes {
+        RealmojiCreationTimes {
+            selected: selections
+                .iter()
+                .filter_map(|s| {
+                    s.creation_time
+                        .map(|t| (s.image_path.clone(), (t, s.utc_offset)))
+                })
+                .collect(),
+        }
+    }
+
+    fn output_spec(
+        &self,
+        moji: &BerealRealmojiRecord,
+        folder: PathBuf,
+        file_name_prefix: String,
+    ) -> OutputRealmojiSpec {
+        let (creation_time, utc_offset) = *self
+            .selected
+            .get(&moji.image_path)
+            .unwrap_or(&(moji.post_time, moji.utc_offset));
+        OutputRealmojiSpec {
+            folder,
+            file_name_prefix,
+            image_file: moji.image_path.clone(),
+            emoji: moji.emoji.clone(),
+            is_instant: moji.is_instant,
+            // not mapped => instant realmoji => post time is the creation time
+            creation_time,
+            utc_offset,
+        }
+    }
+}
+
+/// values of a realmoji template, `index` counts the exported realmojis
+struct RealmojiValues<'a> {
+    spec: &'a OutputRealmojiSpec,
+    index: usize,
+    variants: EmojiVariants,
+}
+
+impl TemplateValues for RealmojiValues<'_> {
+    fn value(&self, name: &str) -> Value {
+        match name {
+            "index" => Value::Number(self.index as i64),
+            "emoji" => Value::Text(emoji_folder_name(&self.spec.emoji, self.variants)),
+            "instant" => Value::Flag(self.spec.is_instant),
+            _ => time_value(&self.spec.creation_time, name),
+        }
+    }
+}
+
+fn emoji_folder_name(emoji: &str, variants: EmojiVariants) -> String {
+    match variants {
+        EmojiVariants::Keep => emoji::folder_name(emoji),
+        EmojiVariants::Merge => emoji::folder_name(&emoji::base_emoji(emoji)),
+    }
+}
+
+/// `template` replaces both the `grouping` and the default file names
 pub fn group_realmojis(
     mojis: &Vec<BerealRealmojiRecord>,
-    grouping: RealmojiGrouping,
-) -> Result<Vec<OutputRealmojiSpec>, String> {
-    Ok(match grouping {
-        RealmojiGrouping::None => extract_realmoji_images(mojis),
-        RealmojiGrouping::Instant => realmoji_group_by(
-            mojis,
-            |mj| mj.is_instant,
-            |key| {
-                if *key {
-                    "instant".to_owned()
-                } else {
-                    "non-instant".to_owned()
-                }
-            },
-        ),
-        RealmojiGrouping::Emoji => realmoji_group_by(
-            mojis,
-            |mj| mj.emoji.clone(),
-            |key| {
-                Name::of(key.chars().collect::<Vec<char>>()[0])
-                    .map(|v| v.to_string().to_lowercase().replace(' ', "-"))
-                    .unwrap_or("unknown-character".to_owned())
-            },
-        ),
-    })
+    selections: &[BerealRealmojiSelectionRecord],
+    groupings: &[RealmojiGrouping],
+    template: Option<&Template>,
+    variants: EmojiVariants,
+) -> Result<Vec<OutputRealmojiSpec>, BerealError> {
+    let times = RealmojiCreationTimes::new(selections);
+    if let Some(template) = template {
+        return Ok(realmoji_template(mojis, &times, template, variants));
+    }
+    // groupings nest in the given order, e.g. emoji,year => "OUTPUT/emoji/year"
+    Ok(realmoji_group_by(mojis, &times, |spec| {
+        // year, month and day levels already in the chain are not repeated (year,month => year/month)
+        let mut date_depth = 0;
+        groupings
+            .iter()
+            .map(|grouping| realmoji_folder(grouping, spec, variants, &mut date_depth))
+            .collect()
+    }))
 }
 
-fn extract_realmoji_images(mojis: &Vec<BerealRealmojiRecord>) -> Vec<OutputRealmojiSpec> {
-    let mut path_strings = HashSet::new();
+/// dates are taken from the creation time (as in the metadata) in the local time zone,
+/// `date_depth` - year, month and day levels added by the previous groupings
+fn realmoji_folder(
+    grouping: &RealmojiGrouping,
+    spec: &OutputRealmojiSpec,
+    variants: EmojiVariants,
+    date_depth: &mut usize,
+) -> PathBuf {
+    let time = spec.creation_time;
+    let date_levels = |target: usize, depth: &mut usize| {
+        let levels = [
+            format!("{:04}", time.year()),
+            format!("{:02}", time.month()),
+            format!("{:02}", time.day()),
+        ];
+        let folder = levels[(*depth).min(target)..target].iter().collect();
+        *depth = max(*depth, target);
+        folder
+    };
+    match grouping {
+        RealmojiGrouping::None => PathBuf::new(),
+        RealmojiGrouping::Instant => PathBuf::from(if spec.is_instant {
+            "instant"
+        } else {
+            "non-instant"
+        }),
+        RealmojiGrouping::Emoji => PathBuf::from(emoji_folder_name(&spec.emoji, variants)),
+        RealmojiGrouping::Year => date_levels(1, date_depth),
+        RealmojiGrouping::Month => date_levels(2, date_depth),
+        RealmojiGrouping::Day => date_levels(3, date_depth),
+        RealmojiGrouping::DayFlat => PathBuf::from(format!(
+            "{:04}-{:02}-{:02}",
+            time.year(),
+            time.month(),
+            time.day()
+        )),
+    }
+}
 
+/// the same image is exported once per folder
+fn realmoji_template(
+    mojis: &Vec<BerealRealmojiRecord>,
+    times: &RealmojiCreationTimes,
+    template: &Template,
+    variants: EmojiVariants,
+) -> Vec<OutputRealmojiSpec> {
+    let mut exported = HashSet::new();
     let mut result = vec![];
 
     for moji in mojis {
-        if !path_strings.insert(moji.image_path.to_string_lossy()) {
+        let mut spec = times.output_spec(moji, PathBuf::new(), String::new());
+        let (folder, file_name_prefix) = template.render(&RealmojiValues {
+            spec: &spec,
+            index: result.len(),
+            variants,
+        });
+        if !exported.insert((folder.clone(), moji.image_path.clone())) {
             continue;
         }
-
-        result.push(OutputRealmojiSpec {
-            file_name_prefix: format!("realmoji-{}", result.len()),
-            folder: PathBuf::new(),
-            image_file: moji.image_path.clone(),
-        })
+        spec.folder = folder;
+        spec.file_name_prefix = file_name_prefix;
+        result.push(spec);
     }
 
     result
 }
 
-fn realmoji_group_by<T, F, FolderFn>(
+/// the same image is exported once per folder
+fn realmoji_group_by<FolderFn>(
     mojis: &Vec<BerealRealmojiRecord>,
-    key_extractor: F,
-    key_to_folder_name: FolderFn,
+    times: &RealmojiCreationTimes,
+    folder: FolderFn,
 ) -> Vec<OutputRealmojiSpec>
 where
-    F: Fn(&BerealRealmojiRecord) -> T,
-    T: Hash,
-    T: std::cmp::Eq,
-    T: Clone,
-    FolderFn: Fn(&T) -> String,
+    FolderFn: Fn(&OutputRealmojiSpec) -> PathBuf,
 {
-    let mut moji_counter = 0;
-
     let mut result = vec![];
-    let mut contains_set = HashMap::new();
+    let mut contains_set: HashMap<PathBuf, HashSet<PathBuf>> = HashMap::new();
 
     for moji in mojis {
-        let key = key_extractor(moji);
-
-        if !contains_set.contains_key(&key) {
-            contains_set.insert(key.clone(), HashSet::new());
-        }
-        let target_set = contains_set.get_mut(&key).unwrap();
+        let mut spec =
+            times.output_spec(moji, PathBuf::new(), format!("realmoji-{}", result.len()));
+        spec.folder = folder(&spec);
 
-        if !target_set.insert(moji.image_path.to_string_lossy()) {
+        let target_set = contains_set.entry(spec.folder.clone()).or_default();
+        if !target_set.insert(moji.image_path.clone()) {
             continue;
         }
-        result.push(OutputRealmojiSpec {
-            file_name_prefix: format!("realmoji-{}", moji_counter),
-            folder: PathBuf::new().join(key_to_folder_name(&key)),
-            image_file: moji.image_path.clone(),
-        });
-        moji_counter += 1;
+        result.push(spec);
     }
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Offset, Utc};
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    fn moment(taken: &str) -> BerealMomentRecord {
+        BerealMomentRecord {
+            front_camera_path: PathBuf::from("front.webp"),
+            back_camera_path: PathBuf::from("back.webp"),
+            caption: None,
+            naive_time_taken: time(taken),
+            utc_offset: Utc.fix(),
+            late: false,
+            song: None,
+            behind_the_scenes: None,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn groups_by_week_weekday_and_attributes() {
+        let mut late = moment("2024-12-29T10:00:00");
+        late.late = true;
+        let moments = vec![
+            late,
+            moment("2024-12-30T10:00:00"),
+            moment("2025-01-05T10:00:00"),
+        ];
+        let folders = |groupings: &[MemoriesGrouping]| {
+            group_moments(&mut moments.clone(), groupings, None)
+                .unwrap()
+                .iter()
+                .map(|s| s.folder.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            folders(&[MemoriesGrouping::Week]),
+            vec![
+                PathBuf::from("2024/W52"),
+                PathBuf::from("2025/W01"),
+                PathBuf::from("2025/W01")
+            ]
+        );
+        assert_eq!(
+            folders(&[MemoriesGrouping::Weekday]),
+            vec![
+                PathBuf::from("Sunday"),
+                PathBuf::from("Monday"),
+                PathBuf::from("Sunday")
+            ]
+        );
+        assert_eq!(
+            folders(&[MemoriesGrouping::Year, MemoriesGrouping::Month]),
+            vec![
+                PathBuf::from("2024/12"),
+                PathBuf::from("2024/12"),
+                PathBuf::from("2025/01")
+            ]
+        );
+        assert_eq!(
+            folders(&[MemoriesGrouping::Month, MemoriesGrouping::Day]),
+            vec![
+                PathBuf::from("2024/12/29"),
+                PathBuf::from("2024/12/30"),
+                PathBuf::from("2025/01/05")
+            ]
+        );
+        assert_eq!(
+            folders(&[MemoriesGrouping::Year, MemoriesGrouping::Late]),
+            vec![
+                PathBuf::from("2024/late"),
+                PathBuf::from("2024/on-time"),
+                PathBuf::from("2025/on-time")
+            ]
+        );
+    }
+
+    #[test]
+    fn realmoji_creation_time_from_selection_or_post() {
+        let mojis = vec![
+            BerealRealmojiRecord {
+                image_path: PathBuf::from("./Photos/realmoji/happy.jpg"),
+                is_instant: false,
+                post_time: time("2024-05-01T10:00:00"),
+                utc_offset: Utc.fix(),
+                emoji: "😃".to_owned(),
+            },
+            BerealRealmojiRecord {
+                image_path: PathBuf::from("./Photos/realmoji/instant.jpg"),
+                is_instant: true,
+                post_time: time("2024-06-01T10:00:00"),
+                utc_offset: Utc.fix(),
+                emoji: "⚡".to_owned(),
+            },
+        ];
+        let selections = vec![BerealRealmojiSelectionRecord {
+            image_path: PathBuf::from("./Photos/realmoji/happy.jpg"),
+            emoji: "😃".to_owned(),
+            creation_time: Some(time("2023-01-01T09:00:00")),
+            utc_offset: Utc.fix(),
+        }];
+
+        let specs = group_realmojis(
+            &mojis,
+            &selections,
+            &[RealmojiGrouping::None],
+            None,
+            EmojiVariants::Keep,
+        )
+        .unwrap();
+        assert_eq!(specs[0].creation_time, time("2023-01-01T09:00:00"));
+        assert_eq!(specs[1].creation_time, time("2024-06-01T10:00:00"));
+
+        let specs = group_realmojis(
+            &mojis,
+            &selections,
+            &[RealmojiGrouping::Emoji, RealmojiGrouping::Year],
+            None,
+            EmojiVariants::Keep,
+        )
+        .unwrap();
+        assert_eq!(
+            specs[0].folder,
+            PathBuf::from("smiling-face-with-open-mouth/2023")
+        );
+        assert_eq!(specs[1].folder, PathBuf::from("high-voltage-sign/2024"));
+
+        let specs = group_realmojis(
+            &mojis,
+            &selections,
+            &[
+                RealmojiGrouping::Year,
+                RealmojiGrouping::Emoji,
+                RealmojiGrouping::Month,
+            ],
+            None,
+            EmojiVariants::Keep,
+        )
+        .unwrap();
+        assert_eq!(
+            specs[0].folder,
+            PathBuf::from("2023/smiling-face-with-open-mouth/01")
+        );
+    }
+}
@@ -1,43 +1,161 @@
 mod args;
+mod composite;
+mod emoji;
+mod error;
 mod export;
 mod filter;
 mod group;
+mod manifest;
+mod mp4;
 pub mod parser;
+mod source;
+mod template;
+#[cfg(test)]
+mod test_dir;
+mod xmp;
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
-use args::Args;
+use args::{Args, ConflictPolicy, PlanFormat, TimeZoneMode};
+use chrono_tz::Tz;
 use clap::Parser;
+use composite::{DualOptions, InsetOptions, Layout};
+use error::{BerealError, ItemReport, Report};
 use export::*;
 use filter::*;
 use group::*;
+use manifest::Manifest;
 use parser::*;
+use source::InputSource;
+use template::{Template, MOMENT_PLACEHOLDERS, REALMOJI_PLACEHOLDERS};
 
 fn main() {
     let args = Args::parse();
-    process(args).unwrap();
+    if let Err(e) = process(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn resolve_time_zone<P: BerealUserParser + ?Sized>(mode: &TimeZoneMode, parser: &P) -> Tz {
+    match mode {
+        TimeZoneMode::Utc => Tz::UTC,
+        TimeZoneMode::Named(tz) => *tz,
+        TimeZoneMode::Auto => parser.get_timezone().unwrap_or_else(|e| {
+            println!(
+                "Warning: cannot determine the export's timezone, using UTC: {}",
+                e
+            );
+            Tz::UTC
+        }),
+    }
+}
+
+fn resolve_export_version(requested: Option<u64>, input: &InputSource) -> Result<u64, BerealError> {
+    if let Some(version) = requested {
+        return Ok(version);
+    }
+    let probe = detect_export_version(input)?;
+    println!("Detected export version {}:", probe.version);
+    for reason in probe.reasons {
+        println!("  - {}", reason);
+    }
+    Ok(probe.version)
+}
+
+/// lists items sharing output file names and applies the --on-conflict policy,
+/// returns the collisions and the items left out of the export
+fn resolve_collisions<T: OutputName + ExportJobGenerator>(
+    specs: &mut Vec<T>,
+    policy: ConflictPolicy,
+    dry_run: &Option<PlanFormat>,
+) -> Result<(Vec<Collision>, Vec<ItemReport>), BerealError> {
+    let collisions = find_collisions(specs);
+    // keeps the JSON plan parseable
+    if !collisions.is_empty() && !matches!(dry_run, Some(PlanFormat::Json)) {
+        println!(
+            "Found {} output name collisions (--on-conflict {}):",
+            collisions.len(),
+            value_name(&policy)
+        );
+        for collision in &collisions {
+            println!(
+                "  - {} ({} items)",
+                collision.output.to_string_lossy(),
+                collision.count
+            );
+        }
+    }
+    if dry_run.is_some() && policy == ConflictPolicy::Fail {
+        // the plan marks the colliding files instead
+        return Ok((collisions, vec![]));
+    }
+    let skipped = apply_conflict_policy(specs, &collisions, policy)?;
+    Ok((collisions, skipped))
+}
+
+fn write_report(
+    path: &Option<String>,
+    command: &str,
+    total: usize,
+    summary: ExportSummary,
+    skipped: Vec<ItemReport>,
+    collisions: Vec<Collision>,
+) -> Result<(), BerealError> {
+    if let Some(path) = path {
+        let mut items = skipped;
+        items.extend(summary.failures);
+        let report = Report {
+            command: command.to_string(),
+            total,
+            exported: summary.exported,
+            items,
+            collisions,
+        };
+        report.write(&PathBuf::from(path))?;
+    }
+    Ok(())
 }
 
-fn process(args: Args) -> Result<(), String> {
-    let input_path = PathBuf::from(args.input);
+fn process(args: Args) -> Result<(), BerealError> {
+    let input = Arc::new(InputSource::open(&PathBuf::from(args.input))?);
     let output_folder = PathBuf::from(&args.output);
     let para_coeff = args.parallelism as f32 / 100.0;
     match args.command {
         args::Commands::Memories {
             image_format,
+            encoding,
             group,
+            template,
             caption,
             interval,
             desc_prefix,
             desc_suffix,
+            no_location,
+            layout,
+            inset_corner,
+            inset_size,
+            inset_border,
+            inset_radius,
+            gutter,
+            background,
         } => {
-            let parser = get_memories_parser(args.export_version, &input_path);
+            let template = template
+                .map(|t| Template::parse(&t, &MOMENT_PLACEHOLDERS))
+                .transpose()?;
+            let version = resolve_export_version(args.export_version, &input)?;
+            let parser = get_memories_parser(version, input.clone());
             parser.check_memories_files()?;
-            // TODO: use this either in the image pasing phase or in the filtering phase
-            // (timestamps are in UTC)
-            let _tz = parser.get_timezone();
+            let tz = resolve_time_zone(&args.time_zone, parser.as_ref());
 
-            let data = parser.parse_memories()?;
+            let Parsed {
+                records: mut data,
+                mut skipped,
+            } = parser.parse_memories()?;
+            data.iter_mut().for_each(|m| m.localize(&tz));
+            if no_location {
+                data.iter_mut().for_each(|m| m.location = None);
+            }
             if args.verbose {
                 println!("Total parsed moments: {}", data.len());
             }
@@ -48,72 +166,180 @@ fn process(args: Args) -> Result<(), String> {
                 println!("Filtered moments: {}", filtered);
             }
 
-            let grouped_moments = group_moments(&mut data, group)?;
+            let mut grouped_moments = group_moments(&mut data, &group, template.as_ref())?;
             if grouped_moments.len() != filtered {
                 print!("Warning: grouping phase omitted data!");
             }
+            let (collisions, conflicting) =
+                resolve_collisions(&mut grouped_moments, args.on_conflict, &args.dry_run)?;
+            let total = filtered;
+            let export_params = ExportParameters {
+                image_format,
+                encoding,
+                desc_prefix,
+                desc_suffix,
+                disable_metadata: args.no_meta || args.strip_metadata,
+                merge_metadata: args.merge_metadata,
+                strip_metadata: args.strip_metadata,
+                xmp_sidecars: args.xmp,
+                set_file_times: args.set_file_times,
+                layout: match layout {
+                    args::MomentLayout::Separate => Layout::Separate,
+                    args::MomentLayout::Composite => Layout::Inset(InsetOptions {
+                        corner: inset_corner,
+                        size: inset_size,
+                        border: inset_border,
+                        radius: inset_radius,
+                    }),
+                    args::MomentLayout::SideBySide => Layout::Dual(DualOptions {
+                        direction: args::DualDirection::Horizontal,
+                        gutter,
+                        background,
+                    }),
+                    args::MomentLayout::Stacked => Layout::Dual(DualOptions {
+                        direction: args::DualDirection::Vertical,
+                        gutter,
+                        background,
+                    }),
+                },
+            };
+            if let Some(plan_format) = &args.dry_run {
+                let plan = plan_generic(
+                    &input,
+                    output_folder.clone(),
+                    export_params,
+                    &grouped_moments,
+                );
+                print_plan(&plan, plan_format);
+                return Ok(());
+            }
             if args.verbose {
                 println!("Exporting");
             }
 
-            let exported = export_generic(
+            let manifest = Manifest::open(&output_folder, args.force)?;
+            let summary = export_generic(
+                &input,
                 output_folder.clone(),
-                ExportParameters {
-                    input_path,
-                    image_format,
-                    desc_prefix,
-                    desc_suffix,
-                    disable_metadata: args.no_meta,
-                },
+                export_params,
                 &grouped_moments,
+                &manifest,
                 args.verbose,
                 para_coeff,
             );
+            manifest.compact()?;
 
             if args.verbose {
                 println!(
                     "Fully exported {} out of {} moments",
-                    exported,
-                    grouped_moments.len()
+                    summary.exported, total
                 );
+                println!("Skipped {} up-to-date files", summary.up_to_date);
             }
 
-            Ok(())
+            skipped.extend(conflicting);
+            write_report(
+                &args.report,
+                "memories",
+                total,
+                summary,
+                skipped,
+                collisions,
+            )
         }
         args::Commands::Realmojis {
             group,
+            template,
+            emoji_variants,
             image_format,
+            encoding,
             desc_prefix,
             desc_suffix,
         } => {
-            let parser = get_realmojis_parser(args.export_version, &input_path);
+            let template = template
+                .map(|t| Template::parse(&t, &REALMOJI_PLACEHOLDERS))
+                .transpose()?;
+            let version = resolve_export_version(args.export_version, &input)?;
+            let parser = get_realmojis_parser(version, input.clone());
             parser.check_realmoji_files()?;
-            let mojis = parser.parse_realmojis()?;
+            let tz = resolve_time_zone(&args.time_zone, parser.as_ref());
+
+            let Parsed {
+                records: mut mojis,
+                mut skipped,
+            } = parser.parse_realmojis()?;
+            mojis.iter_mut().for_each(|m| m.localize(&tz));
+            let Parsed {
+                records: mut selections,
+                skipped: skipped_selections,
+            } = parser.parse_realmoji_selections()?;
+            skipped.extend(skipped_selections);
+            selections.iter_mut().for_each(|s| s.localize(&tz));
+            if args.verbose {
+                println!(
+                    "Total parsed realmojis: {}, realmoji selections: {}",
+                    mojis.len(),
+                    selections.len()
+                );
+            }
 
-            let mojis = group_realmojis(&mojis, group)?;
+            let mut mojis = group_realmojis(
+                &mojis,
+                &selections,
+                &group,
+                template.as_ref(),
+                emoji_variants,
+            )?;
+            let total = mojis.len();
+            let (collisions, conflicting) =
+                resolve_collisions(&mut mojis, args.on_conflict, &args.dry_run)?;
+            skipped.extend(conflicting);
+
+            let export_params = ExportParameters {
+                image_format,
+                encoding,
+                desc_prefix,
+                desc_suffix,
+                disable_metadata: args.no_meta || args.strip_metadata,
+                merge_metadata: args.merge_metadata,
+                strip_metadata: args.strip_metadata,
+                xmp_sidecars: args.xmp,
+                set_file_times: args.set_file_times,
+                layout: Layout::Separate,
+            };
+            if let Some(plan_format) = &args.dry_run {
+                let plan = plan_generic(&input, output_folder.clone(), export_params, &mojis);
+                print_plan(&plan, plan_format);
+                return Ok(());
+            }
 
-            let exported = export_generic(
+            let manifest = Manifest::open(&output_folder, args.force)?;
+            let summary = export_generic(
+                &input,
                 output_folder.clone(),
-                ExportParameters {
-                    input_path,
-                    image_format,
-                    desc_prefix,
-                    desc_suffix,
-                    disable_metadata: args.no_meta,
-                },
+                export_params,
                 &mojis,
+                &manifest,
                 args.verbose,
                 para_coeff,
             );
+            manifest.compact()?;
 
             if args.verbose {
                 println!(
                     "Fully exported {} out of {} realmojis",
-                    exported,
-                    mojis.len()
+                    summary.exported, total
                 );
+                println!("Skipped {} up-to-date files", summary.up_to_date);
             }
-            Ok(())
+            write_report(
+                &args.report,
+                "realmojis",
+                total,
+                summary,
+                skipped,
+                collisions,
+            )
         }
     }
 }
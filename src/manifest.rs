@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::BerealError;
+
+/// one exported file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// relative to the output folder
+    pub output: PathBuf,
+    /// relative to the input root
+    pub sources: Vec<PathBuf>,
+    pub source_hash: String,
+    /// everything else affecting the output (format, metadata, ...), serialized
+    pub options: String,
+}
+
+/// Record of the files exported into the output folder, used to skip up-to-date files on later runs.
+///
+/// Stored as JSON lines, each finished file is appended right away, so an interrupted export
+/// can be picked up where it stopped. Later lines override earlier ones for the same output.
+pub struct Manifest {
+    folder: PathBuf,
+    previous: HashMap<PathBuf, ManifestEntry>,
+    state: Mutex<ManifestState>,
+}
+
+struct ManifestState {
+    entries: HashMap<PathBuf, ManifestEntry>,
+    log: File,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = ".bereal-export-manifest.jsonl";
+
+    /// opens the manifest in the output folder, `ignore_existing` starts from scratch (re-exports everything)
+    pub fn open(output_folder: &Path, ignore_existing: bool) -> Result<Manifest, BerealError> {
+        fs::create_dir_all(output_folder).map_err(|e| BerealError::io(output_folder, e))?;
+        let path = output_folder.join(Self::FILE_NAME);
+        let previous = if ignore_existing {
+            HashMap::new()
+        } else {
+            read_entries(&path)?
+        };
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| BerealError::io(&path, e))?;
+        // an interrupted run may leave a partial line behind, do not append to it
+        let content = fs::read(&path).map_err(|e| BerealError::io(&path, e))?;
+        if content.last().is_some_and(|c| *c != b'\n') {
+            log.write_all(b"\n")
+                .map_err(|e| BerealError::io(&path, e))?;
+        }
+        Ok(Manifest {
+            folder: output_folder.to_path_buf(),
+            state: Mutex::new(ManifestState {
+                entries: previous.clone(),
+                log,
+            }),
+            previous,
+        })
+    }
+
+    /// `contents` of the `sources`, in the same order
+    pub fn entry(
+        &self,
+        output: &Path,
+        sources: &[&Path],
+        contents: &[Vec<u8>],
+        options: String,
+    ) -> ManifestEntry {
+        let mut hasher = Sha256::new();
+        for content in contents {
+            hasher.update(content);
+        }
+        ManifestEntry {
+            output: output
+                .strip_prefix(&self.folder)
+                .unwrap_or(output)
+                .to_path_buf(),
+            // records contain paths like `.//Photos/post/...`
+            sources: sources
+                .iter()
+                .map(|source| {
+                    source
+                        .components()
+                        .filter(|c| matches!(c, Component::Normal(_)))
+                        .collect()
+                })
+                .collect(),
+            source_hash: format!("{:x}", hasher.finalize()),
+            options,
+        }
+    }
+
+    /// the same source was exported with the same options by a previous run and the output still exists
+    pub fn is_up_to_date(&self, entry: &ManifestEntry) -> bool {
+        self.previous.get(&entry.output) == Some(entry) && self.folder.join(&entry.output).is_file()
+    }
+
+    pub fn record(&self, entry: ManifestEntry) -> Result<(), BerealError> {
+        let path = self.folder.join(Self::FILE_NAME);
+        let line = serde_json::to_string(&entry).map_err(|e| BerealError::Io {
+            path: path.clone(),
+            message: e.to_string(),
+        })? + "\n";
+        let mut state = self.state.lock().map_err(|_| BerealError::Io {
+            path: path.clone(),
+            message: "manifest lock poisoned".to_string(),
+        })?;
+        state
+            .log
+            .write_all(line.as_bytes())
+            .map_err(|e| BerealError::io(&path, e))?;
+        state.entries.insert(entry.output.clone(), entry);
+        Ok(())
+    }
+
+    /// rewrites the manifest with a single line per output file
+    pub fn compact(self) -> Result<(), BerealError> {
+        let path = self.folder.join(Self::FILE_NAME);
+        let state = self.state.into_inner().map_err(|_| BerealError::Io {
+            path: path.clone(),
+            message: "manifest lock poisoned".to_string(),
+        })?;
+        drop(state.log);
+
+        let mut entries = state.entries.into_values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.output.cmp(&b.output));
+        let mut content = String::new();
+        for entry in entries {
+            content += &serde_json::to_string(&entry).map_err(|e| BerealError::Io {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            content += "\n";
+        }
+        let tmp_path = path.with_extension("jsonl.tmp");
+        fs::write(&tmp_path, content).map_err(|e| BerealError::io(&tmp_path, e))?;
+        fs::rename(&tmp_path, &path).map_err(|e| BerealError::io(&path, e))
+    }
+}
+
+/// unreadable lines (e.g. cut off by an interrupted run) are ignored
+fn read_entries(path: &Path) -> Result<HashMap<PathBuf, ManifestEntry>, BerealError> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let content = fs::read_to_string(path).map_err(|e| BerealError::io(path, e))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ManifestEntry>(line).ok())
+        .map(|entry| (entry.output.clone(), entry))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn skips_only_unchanged_outputs() {
+        let dir = TestDir::new("manifest");
+        let folder = dir.join("out");
+
+        let manifest = Manifest::open(&folder, false).unwrap();
+        let output = folder.join("2024/a.png");
+        let entry = manifest.entry(
+            &output,
+            &[Path::new("Photos/post/a.webp")],
+            &[b"abc".to_vec()],
+            "png".into(),
+        );
+        assert_eq!(entry.output, PathBuf::from("2024/a.png"));
+        fs::create_dir_all(folder.join("2024")).unwrap();
+        fs::write(&output, b"png").unwrap();
+        manifest.record(entry.clone()).unwrap();
+        // interrupted run - not compacted, a half-written line at the end
+        drop(manifest);
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(folder.join(Manifest::FILE_NAME))
+            .unwrap();
+        log.write_all(b"{\"output\":").unwrap();
+
+        let manifest = Manifest::open(&folder, false).unwrap();
+        assert!(manifest.is_up_to_date(&entry));
+        manifest.record(entry.clone()).unwrap();
+        let changed = manifest.entry(
+            &output,
+            &[Path::new("Photos/post/a.webp")],
+            &[b"abd".to_vec()],
+            "png".into(),
+        );
+        assert!(!manifest.is_up_to_date(&changed));
+        let other_options = manifest.entry(
+            &output,
+            &[Path::new("Photos/post/a.webp")],
+            &[b"abc".to_vec()],
+            "jpeg".into(),
+        );
+        assert!(!manifest.is_up_to_date(&other_options));
+        manifest.compact().unwrap();
+
+        assert!(Manifest::open(&folder, false)
+            .unwrap()
+            .is_up_to_date(&entry));
+        assert!(!Manifest::open(&folder, true).unwrap().is_up_to_date(&entry));
+    }
+}
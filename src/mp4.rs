@@ -0,0 +1,424 @@
+//! Minimal MP4/QuickTime metadata editing: creation times and iTunes-style tags (description, date, location).
+//! Only the `moov` box is rewritten, the media data is kept as-is.
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::BerealLocation;
+
+/// seconds between 1904-01-01 (MP4 epoch) and 1970-01-01
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+/// boxes containing other boxes which have to be edited
+const CONTAINERS: [&[u8; 4]; 8] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"meta", b"ilst",
+];
+
+pub struct VideoMetadata {
+    pub created: DateTime<FixedOffset>,
+    pub description: Option<String>,
+    pub location: Option<BerealLocation>,
+}
+
+enum Atom {
+    Container {
+        kind: [u8; 4],
+        /// bytes preceding the children (version and flags of `meta`)
+        header: Vec<u8>,
+        children: Vec<Atom>,
+    },
+    Leaf {
+        kind: [u8; 4],
+        payload: Vec<u8>,
+    },
+}
+
+impl Atom {
+    fn kind(&self) -> &[u8; 4] {
+        match self {
+            Atom::Container { kind, .. } | Atom::Leaf { kind, .. } => kind,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let (kind, mut body) = match self {
+            Atom::Container {
+                kind,
+                header,
+                children,
+            } => {
+                let mut body = header.clone();
+                for child in children {
+                    child.write(&mut body);
+                }
+                (kind, body)
+            }
+            Atom::Leaf { kind, payload } => (kind, payload.clone()),
+        };
+        let size = body.len() + 8;
+        if let Ok(size) = u32::try_from(size) {
+            out.extend(size.to_be_bytes());
+            out.extend(kind);
+        } else {
+            out.extend(1u32.to_be_bytes());
+            out.extend(kind);
+            out.extend((size as u64 + 8).to_be_bytes());
+        }
+        out.append(&mut body);
+    }
+}
+
+/// sets the creation and modification times of the movie, its tracks and media,
+/// and the description, date and location tags
+pub fn set_metadata(content: &[u8], meta: &VideoMetadata) -> Result<Vec<u8>, String> {
+    let time = (meta.created.timestamp() + MP4_EPOCH_OFFSET).max(0) as u64;
+    rewrite_moov(content, |moov| {
+        set_times(moov, time)?;
+        set_tags(moov, meta);
+        Ok(())
+    })
+}
+
+/// removes the user data and metadata boxes (tags, location, device info) of the movie and its tracks
+pub fn strip_metadata(content: &[u8]) -> Result<Vec<u8>, String> {
+    rewrite_moov(content, |moov| {
+        remove_user_data(moov);
+        Ok(())
+    })
+}
+
+/// applies `edit` to the parsed `moov` box and writes it back
+fn rewrite_moov<F>(content: &[u8], edit: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce(&mut Atom) -> Result<(), String>,
+{
+    let boxes = box_ranges(content)?;
+    let (moov_start, moov_end) = boxes
+        .iter()
+        .find(|(_, _, kind)| kind == b"moov")
+        .map(|(start, end, _)| (*start, *end))
+        .ok_or("no moov box found")?;
+    let mut moov = parse_atoms(&content[moov_start..moov_end])?
+        .pop()
+        .ok_or("empty moov box")?;
+    edit(&mut moov)?;
+
+    let mut new_moov = vec![];
+    moov.write(&mut new_moov);
+    // media data following the moov box moves, chunk offsets are absolute
+    let delta = new_moov.len() as i64 - (moov_end - moov_start) as i64;
+    if delta != 0 {
+        shift_chunk_offsets(&mut moov, moov_end as u64, delta)?;
+        new_moov.clear();
+        moov.write(&mut new_moov);
+    }
+
+    let mut result = Vec::with_capacity(content.len() + new_moov.len());
+    result.extend(&content[..moov_start]);
+    result.extend(new_moov);
+    result.extend(&content[moov_end..]);
+    Ok(result)
+}
+
+/// top-level boxes as (start, end, type)
+fn box_ranges(content: &[u8]) -> Result<Vec<(usize, usize, [u8; 4])>, String> {
+    let mut ranges = vec![];
+    let mut offset = 0;
+    while offset < content.len() {
+        let (header_len, size, kind) = box_header(&content[offset..])?;
+        let end = box_end(offset, header_len, size, content.len())
+            .ok_or_else(|| format!("invalid size of the box at {offset}"))?;
+        ranges.push((offset, end, kind));
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+/// header length, total box size and type
+fn box_header(data: &[u8]) -> Result<(usize, usize, [u8; 4]), String> {
+    if data.len() < 8 {
+        return Err("truncated box header".to_owned());
+    }
+    let kind = [data[4], data[5], data[6], data[7]];
+    match u32::from_be_bytes([data[0], data[1], data[2], data[3]]) {
+        // extends to the end of the file
+        0 => Ok((8, data.len(), kind)),
+        1 => {
+            let size = data
+                .get(8..16)
+                .ok_or("truncated box header")?
+                .try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| "truncated box header")?;
+            // too large for the address space (32-bit targets)
+            let size = usize::try_from(size).map_err(|_| "box size out of range")?;
+            Ok((16, size, kind))
+        }
+        size => Ok((8, size as usize, kind)),
+    }
+}
+
+/// end of a box starting at `offset`, `None` if its size does not fit the data
+fn box_end(offset: usize, header_len: usize, size: usize, len: usize) -> Option<usize> {
+    offset
+        .checked_add(size)
+        .filter(|end| size >= header_len && *end <= len)
+}
+
+fn parse_atoms(data: &[u8]) -> Result<Vec<Atom>, String> {
+    let mut atoms = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let (header_len, size, kind) = box_header(&data[offset..])?;
+        let end = box_end(offset, header_len, size, data.len())
+            .ok_or_else(|| format!("invalid size of the {} box", String::from_utf8_lossy(&kind)))?;
+        let payload = &data[offset + header_len..end];
+        atoms.push(if CONTAINERS.contains(&&kind) {
+            // ISO `meta` is a full box (version and flags), QuickTime `meta` is not
+            let header_len = if &kind == b"meta" && payload.get(8..12) == Some(b"hdlr") {
+                4
+            } else {
+                0
+            };
+            Atom::Container {
+                kind,
+                header: payload[..header_len].to_vec(),
+                children: parse_atoms(&payload[header_len..])?,
+            }
+        } else {
+            Atom::Leaf {
+                kind,
+                payload: payload.to_vec(),
+            }
+        });
+        offset = end;
+    }
+    Ok(atoms)
+}
+
+/// creation and modification time of `mvhd`, `tkhd` and `mdhd`
+fn set_times(atom: &mut Atom, time: u64) -> Result<(), String> {
+    match atom {
+        Atom::Container { children, .. } => {
+            children.iter_mut().try_for_each(|c| set_times(c, time))
+        }
+        Atom::Leaf { kind, payload } if [b"mvhd", b"tkhd", b"mdhd"].contains(&&*kind) => {
+            match payload.first() {
+                Some(0) if payload.len() >= 12 => {
+                    let time = u32::try_from(time).map_err(|_| "time out of range")?;
+                    payload[4..8].copy_from_slice(&time.to_be_bytes());
+                    payload[8..12].copy_from_slice(&time.to_be_bytes());
+                }
+                Some(1) if payload.len() >= 20 => {
+                    payload[4..12].copy_from_slice(&time.to_be_bytes());
+                    payload[12..20].copy_from_slice(&time.to_be_bytes());
+                }
+                _ => {
+                    return Err(format!(
+                        "unsupported {} box",
+                        String::from_utf8_lossy(&kind[..])
+                    ))
+                }
+            }
+            Ok(())
+        }
+        Atom::Leaf { .. } => Ok(()),
+    }
+}
+
+/// `moov/udta/meta/ilst` tags, existing tags of the same type are replaced
+fn set_tags(moov: &mut Atom, meta: &VideoMetadata) {
+    let mut tags = vec![(*b"\xa9day", meta.created.to_rfc3339())];
+    if let Some(description) = meta.description.as_ref().filter(|d| !d.is_empty()) {
+        tags.push((*b"desc", description.clone()));
+    }
+    if let Some(location) = &meta.location {
+        // ISO 6709
+        tags.push((
+            *b"\xa9xyz",
+            format!("{:+08.4}{:+09.4}/", location.latitude, location.longitude),
+        ));
+    }
+
+    let udta = child_container(moov, b"udta", vec![]);
+    let meta_atom = child_container(udta, b"meta", vec![0; 4]);
+    if let Atom::Container { children, .. } = meta_atom {
+        if !children.iter().any(|c| c.kind() == b"hdlr") {
+            children.insert(0, itunes_handler());
+        }
+    }
+    let ilst = child_container(meta_atom, b"ilst", vec![]);
+    if let Atom::Container { children, .. } = ilst {
+        for (kind, value) in tags {
+            children.retain(|c| c.kind() != &kind);
+            children.push(Atom::Container {
+                kind,
+                header: vec![],
+                children: vec![text_data(&value)],
+            });
+        }
+    }
+}
+
+/// `udta` and `meta` boxes of the movie and its tracks
+fn remove_user_data(atom: &mut Atom) {
+    if let Atom::Container { children, .. } = atom {
+        children.retain(|c| c.kind() != b"udta" && c.kind() != b"meta");
+        children
+            .iter_mut()
+            .filter(|c| c.kind() == b"trak")
+            .for_each(remove_user_data);
+    }
+}
+
+/// finds the child container of the given type, creates it if there is none
+fn child_container<'a>(parent: &'a mut Atom, kind: &[u8; 4], header: Vec<u8>) -> &'a mut Atom {
+    let Atom::Container { children, .. } = parent else {
+        unreachable!("only containers are passed")
+    };
+    let position = children
+        .iter()
+        .position(|c| c.kind() == kind && matches!(c, Atom::Container { .. }));
+    let position = position.unwrap_or_else(|| {
+        children.push(Atom::Container {
+            kind: *kind,
+            header,
+            children: vec![],
+        });
+        children.len() - 1
+    });
+    &mut children[position]
+}
+
+/// `hdlr` box identifying iTunes-style metadata
+fn itunes_handler() -> Atom {
+    let mut payload = vec![0; 8];
+    payload.extend(b"mdirappl");
+    payload.extend([0; 9]);
+    Atom::Leaf {
+        kind: *b"hdlr",
+        payload,
+    }
+}
+
+/// UTF-8 `data` box of a tag
+fn text_data(value: &str) -> Atom {
+    // type 1 - UTF-8, locale 0
+    let mut payload = vec![0, 0, 0, 1, 0, 0, 0, 0];
+    payload.extend(value.as_bytes());
+    Atom::Leaf {
+        kind: *b"data",
+        payload,
+    }
+}
+
+/// moves `stco`/`co64` offsets pointing past `from` by `delta`
+fn shift_chunk_offsets(atom: &mut Atom, from: u64, delta: i64) -> Result<(), String> {
+    match atom {
+        Atom::Container { children, .. } => children
+            .iter_mut()
+            .try_for_each(|c| shift_chunk_offsets(c, from, delta)),
+        Atom::Leaf { kind, payload } if kind == b"stco" || kind == b"co64" => {
+            let width = if kind == b"stco" { 4 } else { 8 };
+            for entry in payload
+                .get_mut(8..)
+                .unwrap_or_default()
+                .chunks_exact_mut(width)
+            {
+                let mut bytes = [0; 8];
+                bytes[8 - width..].copy_from_slice(entry);
+                let offset = u64::from_be_bytes(bytes);
+                if offset < from {
+                    continue;
+                }
+                let shifted = (offset as i64 + delta) as u64;
+                if width == 4 {
+                    let shifted =
+                        u32::try_from(shifted).map_err(|_| "chunk offset out of range")?;
+                    entry.copy_from_slice(&shifted.to_be_bytes());
+                } else {
+                    entry.copy_from_slice(&shifted.to_be_bytes());
+                }
+            }
+            Ok(())
+        }
+        Atom::Leaf { .. } => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut result = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        result.extend(kind);
+        result.extend(payload);
+        result
+    }
+
+    #[test]
+    fn sets_times_and_tags_and_moves_chunk_offsets() {
+        let ftyp = atom(b"ftyp", b"isom\0\0\0\0");
+        let moov = |chunk_offset: u32| {
+            // stco with a single chunk
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend(chunk_offset.to_be_bytes());
+            let stbl = atom(b"stbl", &atom(b"stco", &stco));
+            let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &stbl)));
+            atom(b"moov", &[atom(b"mvhd", &[0; 100]), trak].concat())
+        };
+        // the chunk starts at the mdat payload
+        let chunk_offset = ftyp.len() + moov(0).len() + 8;
+        let mut content = [ftyp, moov(chunk_offset as u32)].concat();
+        content.extend(atom(b"mdat", b"media"));
+
+        let meta = VideoMetadata {
+            created: DateTime::parse_from_rfc3339("2024-01-07T07:00:00-05:00").unwrap(),
+            description: Some("caption".to_owned()),
+            location: None,
+        };
+        let result = set_metadata(&content, &meta).unwrap();
+
+        let boxes = box_ranges(&result).unwrap();
+        let (moov_start, moov_end, _) = boxes[1];
+        let mut moov = parse_atoms(&result[moov_start..moov_end]).unwrap();
+        let Atom::Container { children, .. } = moov.pop().unwrap() else {
+            panic!("moov is a container")
+        };
+        let Atom::Leaf { payload: mvhd, .. } = &children[0] else {
+            panic!("mvhd is a leaf")
+        };
+        // 2024-01-07 12:00:00 UTC
+        assert_eq!(&mvhd[4..8], &3787473600u32.to_be_bytes());
+        assert_eq!(children[2].kind(), b"udta");
+
+        let (mdat_start, _, kind) = boxes[2];
+        assert_eq!(&kind, b"mdat");
+        let stco_pos = result[..moov_end]
+            .windows(4)
+            .position(|w| w == b"stco")
+            .unwrap();
+        let chunk = u32::from_be_bytes(result[stco_pos + 12..stco_pos + 16].try_into().unwrap());
+        assert_eq!(&result[chunk as usize..chunk as usize + 5], b"media");
+        assert_eq!(chunk as usize, mdat_start + 8);
+        assert!(result.windows(7).any(|w| w == b"caption"));
+
+        let stripped = strip_metadata(&result).unwrap();
+        assert!(!stripped.windows(4).any(|w| w == b"udta"));
+        assert_eq!(stripped.len(), content.len());
+        assert!(stripped.ends_with(b"media"));
+    }
+
+    #[test]
+    fn rejects_oversized_boxes() {
+        let mut content = atom(b"ftyp", b"isom\0\0\0\0");
+        // largesize
+        content.extend(1u32.to_be_bytes());
+        content.extend(b"free");
+        content.extend(u64::MAX.to_be_bytes());
+        content.extend(b"data");
+
+        assert!(box_ranges(&content).is_err());
+        assert!(parse_atoms(&content).is_err());
+        assert!(strip_metadata(&content).is_err());
+    }
+}
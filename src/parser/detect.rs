@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use crate::{error::BerealError, source::InputSource};
+
+use super::{parser_v0::ParserV0, parser_v1::ParserV1, PARSER_COUNT};
+
+/// how well a parser version fits the export, the higher the score, the better
+pub struct ParserProbe {
+    pub version: u64,
+    pub score: u32,
+    /// human-readable findings that led to the score
+    pub reasons: Vec<String>,
+}
+
+impl ParserProbe {
+    pub fn new(version: u64) -> ParserProbe {
+        ParserProbe {
+            version,
+            score: 0,
+            reasons: vec![],
+        }
+    }
+
+    /// records a finding, `weight` is added to the score
+    pub fn note(&mut self, weight: u32, reason: String) {
+        self.score += weight;
+        self.reasons.push(reason);
+    }
+}
+
+/// keys of the first item of a JSON array file, empty if the file is missing or not an array of objects
+pub fn first_item_keys(input: &InputSource, path: &Path) -> Vec<String> {
+    input
+        .read(path)
+        .ok()
+        .and_then(|bytes| {
+            serde_json::from_slice::<Vec<serde_json::Map<String, serde_json::Value>>>(&bytes).ok()
+        })
+        .and_then(|items| items.into_iter().next())
+        .map(|item| item.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// probes all parser versions, returns the best fitting one
+pub fn detect_export_version(input: &InputSource) -> Result<ParserProbe, BerealError> {
+    let probes: Vec<ParserProbe> = (0..PARSER_COUNT)
+        .map(|version| match version {
+            0 => ParserV0::probe(input),
+            1 => ParserV1::probe(input),
+            _ => panic!("Sanity check failed"),
+        })
+        .collect();
+
+    probes
+        .into_iter()
+        .filter(|p| p.score > 0)
+        // the newest version wins on a tie
+        .max_by_key(|p| (p.score, p.version))
+        .ok_or_else(|| BerealError::InvalidInput {
+            message:
+                "cannot detect the export version, the input does not look like a BeReal export"
+                    .to_string(),
+        })
+}
@@ -1,10 +1,23 @@
+mod detect;
 mod parser_v0;
+mod parser_v1;
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use chrono::{FixedOffset, NaiveDateTime, Offset, TimeZone};
 use chrono_tz::Tz;
 
-pub const PARSER_COUNT: u64 = 1;
+use crate::{
+    error::{BerealError, ItemReport},
+    source::InputSource,
+};
+
+pub use detect::{detect_export_version, ParserProbe};
+
+pub const PARSER_COUNT: u64 = 2;
 
 #[derive(Debug, Clone)]
 pub struct BerealMomentRecord {
@@ -12,13 +25,17 @@ pub struct BerealMomentRecord {
     pub back_camera_path: PathBuf,
 
     pub caption: Option<String>,
+    /// UTC when parsed, local time after `localize`
     pub naive_time_taken: chrono::NaiveDateTime,
+    pub utc_offset: FixedOffset,
 
     pub late: bool,
 
     pub song: Option<BerealSongData>,
 
     pub behind_the_scenes: Option<BerealBTSData>,
+
+    pub location: Option<BerealLocation>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,53 +43,118 @@ pub enum BerealSongData {
     Spotify { spotify_song_id: String },
 }
 
+/// WGS 84 coordinates in degrees
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct BerealLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
 #[derive(Debug, Clone)]
 pub enum BerealBTSData {
     Video { path: PathBuf },
 }
 
-pub trait BerealMemoriesParser {
-    fn get_timezone(&self) -> Result<Tz, String>;
-    fn parse_memories(&self) -> Result<Vec<BerealMomentRecord>, String>;
-    fn check_memories_files(&self) -> Result<(), String>;
+impl BerealMomentRecord {
+    pub fn localize(&mut self, tz: &Tz) {
+        (self.naive_time_taken, self.utc_offset) = utc_to_local(&self.naive_time_taken, tz);
+    }
+}
+
+/// records parsed from the export, entries which could not be parsed are listed in `skipped`
+#[derive(Debug)]
+pub struct Parsed<T> {
+    pub records: Vec<T>,
+    pub skipped: Vec<ItemReport>,
+}
+
+impl<T> Default for Parsed<T> {
+    fn default() -> Parsed<T> {
+        Parsed {
+            records: vec![],
+            skipped: vec![],
+        }
+    }
+}
+
+impl<T> Parsed<T> {
+    pub fn extend(&mut self, other: Parsed<T>) {
+        self.records.extend(other.records);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+pub trait BerealUserParser {
+    fn get_timezone(&self) -> Result<Tz, BerealError>;
+}
+
+pub trait BerealMemoriesParser: BerealUserParser {
+    fn parse_memories(&self) -> Result<Parsed<BerealMomentRecord>, BerealError>;
+    fn check_memories_files(&self) -> Result<(), BerealError>;
 }
 
 #[derive(Debug, Clone)]
 pub struct BerealRealmojiRecord {
     pub image_path: PathBuf,
     pub is_instant: bool,
+    /// UTC when parsed, local time after `localize`
     pub post_time: chrono::NaiveDateTime,
+    pub utc_offset: FixedOffset,
     pub emoji: String,
 }
 
-pub trait BerealRealmojiParser {
-    fn parse_realmojis(&self) -> Result<Vec<BerealRealmojiRecord>, String>;
-    fn check_realmoji_files(&self) -> Result<(), String>;
+impl BerealRealmojiRecord {
+    pub fn localize(&mut self, tz: &Tz) {
+        (self.post_time, self.utc_offset) = utc_to_local(&self.post_time, tz);
+    }
 }
 
-fn read_file_into_string<P>(path: P) -> Result<String, String>
-where
-    P: AsRef<Path> + Clone,
-{
-    let cloned = path.clone();
-    let f = std::fs::File::open(path);
-    if let Err(e) = f {
-        return Err(format!(
-            "Cannot open file {} - {}",
-            cloned.as_ref().to_string_lossy(),
-            e
-        ));
-    }
+/// an entry of the emoji "selection" history - the images a user set up for each emoji variant
+#[derive(Debug, Clone)]
+pub struct BerealRealmojiSelectionRecord {
+    pub image_path: PathBuf,
+    pub emoji: String,
+    /// UTC when parsed, local time after `localize`
+    pub creation_time: Option<chrono::NaiveDateTime>,
+    pub utc_offset: FixedOffset,
+}
 
-    let mut result: String = "".to_string();
-    if let Err(e) = std::io::Read::read_to_string(&mut f.unwrap(), &mut result) {
-        return Err(format!(
-            "Read error, file {} - {}",
-            cloned.as_ref().to_string_lossy(),
-            e
-        ));
+impl BerealRealmojiSelectionRecord {
+    pub fn localize(&mut self, tz: &Tz) {
+        if let Some(time) = self.creation_time {
+            let (local, offset) = utc_to_local(&time, tz);
+            self.creation_time = Some(local);
+            self.utc_offset = offset;
+        }
     }
-    Ok(result)
+}
+
+pub trait BerealRealmojiParser: BerealUserParser {
+    /// parses the realmoji post history (all posted realmojis, including instant ones)
+    fn parse_realmojis(&self) -> Result<Parsed<BerealRealmojiRecord>, BerealError>;
+    /// parses the realmoji selection history, empty if the dump does not contain it
+    fn parse_realmoji_selections(
+        &self,
+    ) -> Result<Parsed<BerealRealmojiSelectionRecord>, BerealError>;
+    fn check_realmoji_files(&self) -> Result<(), BerealError>;
+}
+
+/// times in the dump are in UTC, returns the local time in `tz` and its offset at that moment
+pub fn utc_to_local(time: &NaiveDateTime, tz: &Tz) -> (NaiveDateTime, FixedOffset) {
+    let local = tz.from_utc_datetime(time);
+    (local.naive_local(), local.offset().fix())
+}
+
+fn read_file_into_string(source: &InputSource, path: &Path) -> Result<String, BerealError> {
+    bytes_into_string(source, path, source.read(path)?)
+}
+
+fn bytes_into_string(
+    source: &InputSource,
+    path: &Path,
+    bytes: Vec<u8>,
+) -> Result<String, BerealError> {
+    String::from_utf8(bytes).map_err(|e| BerealError::parse(&source.display_path(path), e))
 }
 
 fn check_vesion_panic(version: u64) {
@@ -84,20 +166,40 @@ fn check_vesion_panic(version: u64) {
     }
 }
 
-pub fn get_memories_parser(version: u64, input_path: &Path) -> Box<dyn BerealMemoriesParser> {
+pub fn get_memories_parser(version: u64, input: Arc<InputSource>) -> Box<dyn BerealMemoriesParser> {
     check_vesion_panic(version);
 
     match version {
-        0 => Box::new(parser_v0::ParserV0::new(PathBuf::from(input_path))),
+        0 => Box::new(parser_v0::ParserV0::new(input)),
+        1 => Box::new(parser_v1::ParserV1::new(input)),
         _ => panic!("Sanity check failed"),
     }
 }
 
-pub fn get_realmojis_parser(version: u64, input_path: &Path) -> Box<dyn BerealRealmojiParser> {
+pub fn get_realmojis_parser(
+    version: u64,
+    input: Arc<InputSource>,
+) -> Box<dyn BerealRealmojiParser> {
     check_vesion_panic(version);
 
     match version {
-        0 => Box::new(parser_v0::ParserV0::new(PathBuf::from(input_path))),
+        0 => Box::new(parser_v0::ParserV0::new(input)),
+        1 => Box::new(parser_v1::ParserV1::new(input)),
         _ => panic!("Sanity check failed"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_evening_utc_to_local_stays_on_the_same_day() {
+        // 2024-03-02 23:30 in New York
+        let utc =
+            NaiveDateTime::parse_from_str("2024-03-03T04:30:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+        let (local, offset) = utc_to_local(&utc, &chrono_tz::America::New_York);
+        assert_eq!(local.to_string(), "2024-03-02 23:30:00");
+        assert_eq!(offset.to_string(), "-05:00");
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Offset, Utc};
 use chrono_tz::Tz;
 use regex::{Captures, Regex, Replacer};
 use serde::{
@@ -7,130 +7,300 @@ use serde::{
 };
 use std::{
     fmt,
-    path::{absolute, PathBuf},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    error::{BerealError, ItemReport, ItemStatus},
+    source::InputSource,
 };
 
 use super::{
-    BerealBTSData, BerealMemoriesParser, BerealMomentRecord, BerealRealmojiParser,
-    BerealRealmojiRecord, BerealSongData,
+    detect::{first_item_keys, ParserProbe},
+    BerealBTSData, BerealLocation, BerealMemoriesParser, BerealMomentRecord, BerealRealmojiParser,
+    BerealRealmojiRecord, BerealRealmojiSelectionRecord, BerealSongData, BerealUserParser, Parsed,
 };
 
 pub struct ParserV0 {
-    input_path: PathBuf,
+    input: Arc<InputSource>,
 }
 
 impl ParserV0 {
     const MEMORIES_FILE: &'static str = "memories.json";
     const USER_FILE: &'static str = "user.json";
     const REALMOJIS_FILE: &'static str = "realmojis.json";
+    // the name `unzip -B` gives to the second realmojis.json entry
+    const REALMOJIS_BACKUP_FILE: &'static str = "realmojis.json~";
 
     fn relative_path(&self, p: &str) -> PathBuf {
-        self.input_path.join(p)
+        PathBuf::from(p)
+    }
+
+    pub fn new(input: Arc<InputSource>) -> ParserV0 {
+        ParserV0 { input }
     }
 
-    pub fn new(input_path: PathBuf) -> ParserV0 {
-        ParserV0 { input_path }
+    pub fn probe(input: &InputSource) -> ParserProbe {
+        let mut probe = ParserProbe::new(0);
+        let memories = PathBuf::from(ParserV0::MEMORIES_FILE);
+        if !input.is_file(&memories) {
+            return probe;
+        }
+        probe.note(1, format!("{} found", ParserV0::MEMORIES_FILE));
+
+        let keys = first_item_keys(input, &memories);
+        if ["frontImage", "backImage", "takenTime"]
+            .iter()
+            .all(|k| keys.iter().any(|key| key == k))
+        {
+            probe.note(
+                2,
+                format!(
+                    "{} entries contain frontImage, backImage and takenTime",
+                    ParserV0::MEMORIES_FILE
+                ),
+            );
+        }
+        if input.is_file(&PathBuf::from(ParserV0::USER_FILE)) {
+            probe.note(1, format!("{} found", ParserV0::USER_FILE));
+        }
+        if input.is_dir(&PathBuf::from("Photos/post")) {
+            probe.note(1, "Photos/post directory found".to_string());
+        }
+        probe
+    }
+
+    /// collects all realmoji json payloads (and the files they come from), regardless of whether they are
+    /// separate archive entries, side-by-side files or concatenated into a single file
+    fn realmoji_payloads(
+        &self,
+        history: RealmojiHistory,
+    ) -> Result<Vec<(PathBuf, String)>, BerealError> {
+        let mut files = vec![self.relative_path(ParserV0::REALMOJIS_FILE)];
+        let backup = self.relative_path(ParserV0::REALMOJIS_BACKUP_FILE);
+        if self.input.is_file(&backup) {
+            files.push(backup);
+        }
+
+        let mut result = vec![];
+        for file in files {
+            for payload in self.input.read_all(&file)? {
+                let content = super::bytes_into_string(&self.input, &file, payload)?;
+                let display_path = self.input.display_path(&file);
+                let parts = split_concatenated_json(&content)
+                    .map_err(|e| BerealError::parse(&display_path, e))?;
+                for (offset, part) in parts {
+                    let part_history = classify_realmoji_payload(part);
+                    // both histories are read from the same files, warn only once
+                    if part_history == RealmojiHistory::Unknown && history == RealmojiHistory::Posts
+                    {
+                        println!(
+                            "Warning: {} at byte {}: not a list of realmojis, ignored",
+                            display_path.to_string_lossy(),
+                            offset
+                        );
+                    }
+                    if part_history == history {
+                        result.push((display_path.clone(), part.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(result)
     }
 }
 
-impl BerealMemoriesParser for ParserV0 {
-    fn get_timezone(&self) -> Result<Tz, String> {
-        let read_res = super::read_file_into_string(self.relative_path(ParserV0::USER_FILE))?;
+impl BerealUserParser for ParserV0 {
+    fn get_timezone(&self) -> Result<Tz, BerealError> {
+        let user_file = self.relative_path(ParserV0::USER_FILE);
+        let read_res = super::read_file_into_string(&self.input, &user_file)?;
 
         let u_json = serde_json::from_str::<UserJson>(&read_res)
-            .map_err(|e| format!("Error parsing user.json file: {}", e))?;
+            .map_err(|e| BerealError::parse(&self.input.display_path(&user_file), e))?;
 
         println!(
             "Info: username = {}, timezone = {}",
             u_json.username, u_json.timezone,
         );
-        if let Ok(path) =
-            absolute(self.relative_path(&("./".to_owned() + &u_json.profile_picture.path)))
-        {
-            println!("profile picture path: {}", path.to_string_lossy());
-        }
+        let picture = self.relative_path(&("./".to_owned() + &u_json.profile_picture.path));
+        println!(
+            "profile picture path: {}",
+            self.input.display_path(&picture).to_string_lossy()
+        );
 
-        let tz: Tz = u_json.timezone.parse().unwrap();
+        let tz: Tz = u_json.timezone.parse().map_err(|e| {
+            BerealError::parse(
+                &self.input.display_path(&user_file),
+                format!("unknown timezone {} - {}", u_json.timezone, e),
+            )
+        })?;
 
         Ok(tz)
     }
+}
 
-    fn check_memories_files(&self) -> Result<(), String> {
+impl BerealMemoriesParser for ParserV0 {
+    fn check_memories_files(&self) -> Result<(), BerealError> {
         let required_files = vec![self.relative_path(ParserV0::MEMORIES_FILE)];
 
         let warn_if_missing_files = vec![self.relative_path(ParserV0::USER_FILE)];
 
-        check_files(&required_files, &warn_if_missing_files)
+        check_files(&self.input, &required_files, &warn_if_missing_files)
     }
-    fn parse_memories(&self) -> Result<Vec<BerealMomentRecord>, String> {
+    fn parse_memories(&self) -> Result<Parsed<BerealMomentRecord>, BerealError> {
+        let memories_file = self.relative_path(ParserV0::MEMORIES_FILE);
         parse_generic::<MemoryItemJson, super::BerealMomentRecord>(
-            &self.relative_path(ParserV0::MEMORIES_FILE),
+            &super::read_file_into_string(&self.input, &memories_file)?,
+            &self.input.display_path(&memories_file),
         )
     }
 }
 
 impl BerealRealmojiParser for ParserV0 {
-    fn parse_realmojis(&self) -> Result<Vec<super::BerealRealmojiRecord>, String> {
-        parse_generic::<RealmojiItemJson, super::BerealRealmojiRecord>(
-            &self.relative_path(ParserV0::REALMOJIS_FILE),
-        )
+    fn parse_realmojis(&self) -> Result<Parsed<super::BerealRealmojiRecord>, BerealError> {
+        let payloads = self.realmoji_payloads(RealmojiHistory::Posts)?;
+        if payloads.is_empty() {
+            return Err(BerealError::parse(
+                &self
+                    .input
+                    .display_path(&self.relative_path(ParserV0::REALMOJIS_FILE)),
+                "no realmoji post history (entries containing \"isInstant\") found",
+            ));
+        }
+
+        let mut result = Parsed::default();
+        for (file, payload) in payloads {
+            result.extend(parse_generic::<
+                RealmojiItemJson,
+                super::BerealRealmojiRecord,
+            >(&payload, &file)?);
+        }
+        Ok(result)
     }
 
-    fn check_realmoji_files(&self) -> Result<(), String> {
+    fn parse_realmoji_selections(
+        &self,
+    ) -> Result<Parsed<super::BerealRealmojiSelectionRecord>, BerealError> {
+        let mut result = Parsed::default();
+        for (file, payload) in self.realmoji_payloads(RealmojiHistory::Selections)? {
+            // the selection history is optional, a broken one only costs the creation times
+            match parse_generic::<RealmojiSelectionItemJson, super::BerealRealmojiSelectionRecord>(
+                &payload, &file,
+            ) {
+                Ok(parsed) => result.extend(parsed),
+                Err(e) => {
+                    println!("Warning: realmoji selection history ignored: {}", e);
+                    result.skipped.push(ItemReport {
+                        item: file.to_string_lossy().to_string(),
+                        status: ItemStatus::Skipped,
+                        errors: vec![e],
+                    });
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn check_realmoji_files(&self) -> Result<(), BerealError> {
         let required_files = vec![self.relative_path(ParserV0::REALMOJIS_FILE)];
 
-        check_files(&required_files, &vec![])
+        check_files(&self.input, &required_files, &vec![])
     }
 }
 
-fn parse_generic<JsonParseType, OutType>(input_path: &PathBuf) -> Result<Vec<OutType>, String>
+/// `file` identifies the parsed file in errors
+fn parse_generic<JsonParseType, OutType>(
+    read_res: &str,
+    file: &Path,
+) -> Result<Parsed<OutType>, BerealError>
 where
     for<'a> &'a JsonParseType: TryInto<OutType, Error = String>,
     for<'a> JsonParseType: Deserialize<'a>,
 {
-    let read_res = super::read_file_into_string(input_path)?;
     let parsed: Vec<JsonParseType> =
-        serde_json::from_str(&read_res).map_err(|e| format!("Failed to parse item: {}", e))?;
+        serde_json::from_str(read_res).map_err(|e| BerealError::parse(file, e))?;
 
     let pre_result: Vec<Result<OutType, String>> = parsed.iter().map(|x| x.try_into()).collect();
-    let mut result = vec![];
-    let mut errors = false;
-
-    for r in pre_result {
-        if let Err(e) = r {
-            println!("Error when parsing an entry: {}", e);
-            errors = true;
-        } else {
-            result.push(r.unwrap());
+    let mut result = Parsed::default();
+
+    for (index, r) in pre_result.into_iter().enumerate() {
+        match r {
+            Err(e) => {
+                println!("Error when parsing an entry: {}", e);
+                result.skipped.push(ItemReport {
+                    item: format!("{} entry #{}", file.to_string_lossy(), index),
+                    status: ItemStatus::Skipped,
+                    errors: vec![BerealError::parse(file, e)],
+                });
+            }
+            Ok(v) => result.records.push(v),
         }
     }
 
-    if errors {
+    if !result.skipped.is_empty() {
         println!("Errors present, check output");
     }
 
     Ok(result)
 }
 
-fn check_files(required_files: &Vec<PathBuf>, warn_files: &Vec<PathBuf>) -> Result<(), String> {
+/// splits a file containing several JSON documents written one after another,
+/// returns the documents with their byte offsets
+fn split_concatenated_json(content: &str) -> Result<Vec<(usize, &str)>, String> {
+    let mut result = vec![];
+    let mut stream = serde_json::Deserializer::from_str(content).into_iter::<de::IgnoredAny>();
+    let mut start = 0;
+    while let Some(item) = stream.next() {
+        item.map_err(|e| format!("Failed to parse item: {}", e))?;
+        let end = stream.byte_offset();
+        let document = &content[start..end];
+        let leading = document.len() - document.trim_start().len();
+        result.push((start + leading, document.trim()));
+        start = end;
+    }
+    Ok(result)
+}
+
+#[derive(PartialEq, Debug)]
+enum RealmojiHistory {
+    /// realmoji-post history, every item has the "isInstant" field
+    Posts,
+    /// realmoji-emoji (selection) history
+    Selections,
+    /// no items, nothing to tell them apart by
+    Empty,
+    /// not a list of objects
+    Unknown,
+}
+
+fn classify_realmoji_payload(payload: &str) -> RealmojiHistory {
+    match serde_json::from_str::<Vec<serde_json::Map<String, serde_json::Value>>>(payload) {
+        Err(_) => RealmojiHistory::Unknown,
+        Ok(items) if items.is_empty() => RealmojiHistory::Empty,
+        Ok(items) if items.iter().any(|i| i.contains_key("isInstant")) => RealmojiHistory::Posts,
+        Ok(_) => RealmojiHistory::Selections,
+    }
+}
+
+fn check_files(
+    input: &InputSource,
+    required_files: &Vec<PathBuf>,
+    warn_files: &Vec<PathBuf>,
+) -> Result<(), BerealError> {
     for required in required_files {
-        if !required.exists() {
-            return Err(format!(
-                "Required file {} does not exist",
-                required.to_string_lossy()
-            ));
-        } else if !required.is_file() {
-            return Err(format!(
-                "{} is not a file, file expected",
-                required.to_string_lossy()
-            ));
+        if !input.is_file(required) {
+            return Err(BerealError::MissingFile {
+                path: input.display_path(required),
+            });
         }
     }
     for warn_f in warn_files {
-        if !warn_f.exists() {
+        if !input.is_file(warn_f) {
             println!(
                 "Warning: non-vital file {} does not exist",
-                warn_f.to_string_lossy()
+                input.display_path(warn_f).to_string_lossy()
             )
         }
     }
@@ -163,6 +333,12 @@ enum Music {
     Unknown(serde_json::Value),
 }
 
+#[derive(Deserialize, Clone)]
+struct LocationJson {
+    latitude: f64,
+    longitude: f64,
+}
+
 #[derive(Deserialize, Clone)]
 struct MediaInfo {
     path: String,
@@ -182,6 +358,7 @@ struct MemoryItemJson {
     pub caption: Option<String>,
     #[serde(alias = "takenTime")]
     pub time_taken: NaiveTimeWrap,
+    pub location: Option<LocationJson>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -194,6 +371,17 @@ struct RealmojiItemJson {
     pub post_time: NaiveTimeWrap,
 }
 
+#[derive(Deserialize, Clone)]
+struct RealmojiSelectionItemJson {
+    pub media: MediaInfo,
+    pub emoji: String,
+    #[serde(alias = "createdAt")]
+    pub created_at: Option<NaiveTimeWrap>,
+    /// older exports only have the time of the last update
+    #[serde(alias = "updatedAt")]
+    pub updated_at: Option<NaiveTimeWrap>,
+}
+
 #[derive(Clone)]
 struct NaiveTimeWrap {
     time: NaiveDateTime,
@@ -327,11 +515,25 @@ impl TryInto<BerealMomentRecord> for &MemoryItemJson {
             Some(result.unwrap())
         });
 
+        let location = self.location.as_ref().and_then(|l| {
+            if (-90.0..=90.0).contains(&l.latitude) && (-180.0..=180.0).contains(&l.longitude) {
+                Some(BerealLocation {
+                    latitude: l.latitude,
+                    longitude: l.longitude,
+                })
+            } else {
+                println!("Invalid location {}, {}, skipping", l.latitude, l.longitude);
+                None
+            }
+        });
+
         Ok(BerealMomentRecord {
             back_camera_path: back_path,
             front_camera_path: front_path,
             caption: self.caption.clone(),
             naive_time_taken: self.time_taken.time,
+            utc_offset: Utc.fix(),
+            location,
             late: self.late,
             song,
             behind_the_scenes: self.bts_media.as_ref().and_then(|bts| match bts {
@@ -367,25 +569,183 @@ impl TryInto<BerealSongData> for &Music {
     }
 }
 
+/// the emoji is a grapheme cluster, e.g. skin tones, flags and ZWJ sequences (❤️‍🔥) are kept whole
+fn realmoji_emoji(emoji: &str) -> Result<String, String> {
+    let mut graphemes = emoji.graphemes(true);
+    let first = graphemes
+        .next()
+        .ok_or("Realmoji has no emoji association".to_string())?;
+    if graphemes.next().is_some() {
+        println!(
+            "Warning, realmoji {} has more than one emoji, other emoji are ignored!",
+            emoji
+        )
+    }
+    Ok(first.to_string())
+}
+
 impl TryInto<BerealRealmojiRecord> for &RealmojiItemJson {
     type Error = String;
 
     fn try_into(self) -> Result<BerealRealmojiRecord, Self::Error> {
-        let emoji_chars = self.emoji.chars().collect::<Vec<char>>();
-        if emoji_chars.len() > 1 {
-            println!(
-                "Warning, emoji {} longer than 2 characers, other characters are ignored!",
-                self.emoji
-            )
-        } else if emoji_chars.is_empty() {
-            return Err("Realmoji has no emoji association".to_string());
-        }
-
         Ok(BerealRealmojiRecord {
-            emoji: emoji_chars[0].to_string(),
+            emoji: realmoji_emoji(&self.emoji)?,
             image_path: bereal_path_sanitize_to_pathbuf(&self.media.path)?,
             is_instant: self.instant,
             post_time: self.post_time.time,
+            utc_offset: Utc.fix(),
+        })
+    }
+}
+
+impl TryInto<BerealRealmojiSelectionRecord> for &RealmojiSelectionItemJson {
+    type Error = String;
+
+    fn try_into(self) -> Result<BerealRealmojiSelectionRecord, Self::Error> {
+        Ok(BerealRealmojiSelectionRecord {
+            emoji: realmoji_emoji(&self.emoji)?,
+            image_path: bereal_path_sanitize_to_pathbuf(&self.media.path)?,
+            creation_time: self
+                .created_at
+                .as_ref()
+                .or(self.updated_at.as_ref())
+                .map(|t| t.time),
+            utc_offset: Utc.fix(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        args::{EmojiVariants, RealmojiGrouping},
+        test_dir::TestDir,
+    };
+
+    const POSTS: &str = r#"[{"media": {"path": "/Photos/x/realmoji/a.jpg"}, "emoji": "😃",
+        "isInstant": false, "postedAt": "2024-07-22T09:11:05.339Z"}]"#;
+    const SELECTIONS: &str = r#"[{"media": {"path": "/Photos/x/realmoji/a.jpg"}, "emoji": "😃",
+        "type": "happy", "createdAt": "2023-01-02T03:04:05.000Z"}]"#;
+
+    #[test]
+    fn keeps_emoji_sequences() {
+        for emoji in ["👍🏽", "❤️\u{200d}🔥", "🇨🇿", "1️⃣"] {
+            assert_eq!(realmoji_emoji(emoji), Ok(emoji.to_string()));
+        }
+        assert_eq!(realmoji_emoji("😃😃"), Ok("😃".to_string()));
+        assert!(realmoji_emoji("").is_err());
+    }
+
+    #[test]
+    fn splits_concatenated_realmoji_files() {
+        let concatenated = format!("{}\n{}", SELECTIONS, POSTS);
+        let parts = split_concatenated_json(&concatenated).unwrap();
+        assert_eq!(parts, vec![(0, SELECTIONS), (SELECTIONS.len() + 1, POSTS)]);
+    }
+
+    #[test]
+    fn classifies_realmoji_histories() {
+        assert_eq!(classify_realmoji_payload(POSTS), RealmojiHistory::Posts);
+        assert_eq!(
+            classify_realmoji_payload(SELECTIONS),
+            RealmojiHistory::Selections
+        );
+        assert_eq!(classify_realmoji_payload("[]"), RealmojiHistory::Empty);
+        assert_eq!(classify_realmoji_payload("{}"), RealmojiHistory::Unknown);
+        assert_eq!(classify_realmoji_payload("[1]"), RealmojiHistory::Unknown);
+    }
+
+    #[test]
+    fn parses_selection_history() {
+        let parsed = parse_generic::<RealmojiSelectionItemJson, BerealRealmojiSelectionRecord>(
+            SELECTIONS,
+            Path::new("realmojis.json"),
+        )
+        .unwrap()
+        .records;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(
+            parsed[0].image_path,
+            PathBuf::from("./Photos/realmoji/a.jpg")
+        );
+        assert!(parsed[0].creation_time.is_some());
+    }
+
+    #[test]
+    fn prefers_creation_over_update_time() {
+        let selections = r#"[{"emoji": "😃", "media": {"bucket": "storage.bere.al", "height": 500,
+            "path": "/Photos/x/realmoji/a.jpg", "width": 500}, "type": "happy",
+            "createdAt": "2023-01-02T03:04:05.000Z", "updatedAt": "2024-02-03T04:05:06.000Z"},
+            {"emoji": "😍", "media": {"bucket": "storage.bere.al", "height": 500,
+            "path": "/Photos/x/realmoji/b.jpg", "width": 500}, "type": "heartEyes",
+            "updatedAt": "2024-02-03T04:05:06.000Z"}]"#;
+        let parsed = parse_generic::<RealmojiSelectionItemJson, BerealRealmojiSelectionRecord>(
+            selections,
+            Path::new("realmojis.json"),
+        )
+        .unwrap()
+        .records;
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+        assert_eq!(parsed[0].creation_time, Some(time("2023-01-02T03:04:05")));
+        assert_eq!(parsed[1].creation_time, Some(time("2024-02-03T04:05:06")));
+    }
+
+    #[test]
+    fn infers_realmoji_creation_times_from_an_export() {
+        let posts = r#"[{"emoji": "😃", "isInstant": false, "media": {"bucket": "storage.bere.al",
+            "height": 500, "path": "/Photos/x/realmoji/a.jpg", "width": 500},
+            "postedAt": "2024-07-22T09:11:05.339Z"},
+            {"emoji": "⚡", "isInstant": true, "media": {"bucket": "storage.bere.al",
+            "height": 500, "path": "/Photos/x/realmoji/b.jpg", "width": 500},
+            "postedAt": "2024-08-01T18:30:00.000Z"}]"#;
+        let selections = r#"[{"emoji": "😃", "media": {"bucket": "storage.bere.al", "height": 500,
+            "path": "/Photos/x/realmoji/a.jpg", "width": 500}, "type": "happy",
+            "createdAt": "2023-01-02T03:04:05.000Z", "updatedAt": "2024-02-03T04:05:06.000Z"}]"#;
+        let dir = TestDir::new("realmojis");
+        std::fs::write(
+            dir.join(ParserV0::REALMOJIS_FILE),
+            format!("{selections}\n{posts}"),
+        )
+        .unwrap();
+
+        let parser = ParserV0::new(Arc::new(InputSource::open(&dir).unwrap()));
+        let mojis = parser.parse_realmojis().unwrap().records;
+        let selections = parser.parse_realmoji_selections().unwrap().records;
+        let specs = crate::group::group_realmojis(
+            &mojis,
+            &selections,
+            &[RealmojiGrouping::Year, RealmojiGrouping::Month],
+            None,
+            EmojiVariants::Keep,
+        )
+        .unwrap();
+
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+        // selected in advance => created at the selection, instant => created at the post
+        assert_eq!(specs[0].creation_time, time("2023-01-02T03:04:05"));
+        assert_eq!(specs[0].folder, PathBuf::from("2023/01"));
+        assert_eq!(specs[1].creation_time, time("2024-08-01T18:30:00"));
+        assert_eq!(specs[1].folder, PathBuf::from("2024/08"));
+    }
+
+    #[test]
+    fn ignores_parts_that_are_not_realmoji_lists() {
+        let dir = TestDir::new("realmojis-unknown");
+        std::fs::write(
+            dir.join(ParserV0::REALMOJIS_FILE),
+            format!("{{\"error\": \"not found\"}}\n{POSTS}\n[1, 2]"),
+        )
+        .unwrap();
+
+        let parser = ParserV0::new(Arc::new(InputSource::open(&dir).unwrap()));
+        let mojis = parser.parse_realmojis().unwrap();
+        assert_eq!(mojis.records.len(), 1);
+        assert!(mojis.skipped.is_empty());
+        assert!(parser
+            .parse_realmoji_selections()
+            .unwrap()
+            .records
+            .is_empty());
+    }
+}
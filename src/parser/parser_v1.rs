@@ -0,0 +1,171 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono_tz::Tz;
+
+use crate::{error::BerealError, source::InputSource};
+
+use super::{
+    detect::ParserProbe, parser_v0::ParserV0, BerealBTSData, BerealMemoriesParser,
+    BerealMomentRecord, BerealRealmojiParser, BerealRealmojiRecord, BerealRealmojiSelectionRecord,
+    BerealUserParser, Parsed,
+};
+
+/// Legacy exports (accounts older than December 2022).
+///
+/// The JSON files are the same as in V0, however memories are stored in the `Photos/bereal`
+/// directory which mixes `.jpg` and `.webp` images whose extensions do not always match `memories.json`.
+/// Paths which do not exist are resolved by their stem and an alternative extension.
+pub struct ParserV1 {
+    v0: ParserV0,
+    input: Arc<InputSource>,
+}
+
+impl ParserV1 {
+    const LEGACY_DIR: &'static str = "Photos/bereal";
+    const ALTERNATIVE_EXTENSIONS: [&'static str; 4] = ["webp", "jpg", "jpeg", "png"];
+
+    pub fn new(input: Arc<InputSource>) -> ParserV1 {
+        ParserV1 {
+            v0: ParserV0::new(input.clone()),
+            input,
+        }
+    }
+
+    pub fn probe(input: &InputSource) -> ParserProbe {
+        let mut probe = ParserV0::probe(input);
+        probe.version = 1;
+        if probe.score == 0 || !input.is_dir(&PathBuf::from(ParserV1::LEGACY_DIR)) {
+            probe.score = 0;
+            return probe;
+        }
+        probe.note(
+            2,
+            format!(
+                "legacy {} directory found (mixed jpg/webp images)",
+                ParserV1::LEGACY_DIR
+            ),
+        );
+        probe
+    }
+
+    /// returns the path of an existing file, `None` if there is no candidate
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        if self.input.is_file(path) {
+            return Some(path.to_path_buf());
+        }
+
+        let mut candidates: Vec<PathBuf> = ParserV1::ALTERNATIVE_EXTENSIONS
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .collect();
+        // e.g. abc.jpg.webp - produced by manual conversion (older versions of the README)
+        if let Some(file_name) = path.file_name() {
+            candidates.extend(ParserV1::ALTERNATIVE_EXTENSIONS.iter().map(|ext| {
+                path.with_file_name(format!("{}.{}", file_name.to_string_lossy(), ext))
+            }));
+        }
+
+        candidates.into_iter().find(|c| self.input.is_file(c))
+    }
+
+    /// resolves `path` in place, returns false if it cannot be resolved
+    fn resolve_in_place(&self, path: &mut PathBuf) -> bool {
+        match self.resolve(path) {
+            Some(resolved) => {
+                *path = resolved;
+                true
+            }
+            None => {
+                println!(
+                    "Warning: cannot find {} under any of the extensions {:?}",
+                    self.input.display_path(path).to_string_lossy(),
+                    ParserV1::ALTERNATIVE_EXTENSIONS
+                );
+                false
+            }
+        }
+    }
+}
+
+impl BerealUserParser for ParserV1 {
+    fn get_timezone(&self) -> Result<Tz, BerealError> {
+        self.v0.get_timezone()
+    }
+}
+
+impl BerealMemoriesParser for ParserV1 {
+    fn parse_memories(&self) -> Result<Parsed<BerealMomentRecord>, BerealError> {
+        let mut result = self.v0.parse_memories()?;
+        for moment in result.records.iter_mut() {
+            self.resolve_in_place(&mut moment.front_camera_path);
+            self.resolve_in_place(&mut moment.back_camera_path);
+            if let Some(BerealBTSData::Video { path }) = moment.behind_the_scenes.as_mut() {
+                self.resolve_in_place(path);
+            }
+        }
+        Ok(result)
+    }
+
+    fn check_memories_files(&self) -> Result<(), BerealError> {
+        self.v0.check_memories_files()
+    }
+}
+
+impl BerealRealmojiParser for ParserV1 {
+    fn parse_realmojis(&self) -> Result<Parsed<BerealRealmojiRecord>, BerealError> {
+        let mut result = self.v0.parse_realmojis()?;
+        for moji in result.records.iter_mut() {
+            self.resolve_in_place(&mut moji.image_path);
+        }
+        Ok(result)
+    }
+
+    fn parse_realmoji_selections(
+        &self,
+    ) -> Result<Parsed<BerealRealmojiSelectionRecord>, BerealError> {
+        // selections are only matched against the (resolved) post paths, resolve them the same way
+        let mut result = self.v0.parse_realmoji_selections()?;
+        for selection in result.records.iter_mut() {
+            if let Some(resolved) = self.resolve(&selection.image_path) {
+                selection.image_path = resolved;
+            }
+        }
+        Ok(result)
+    }
+
+    fn check_realmoji_files(&self) -> Result<(), BerealError> {
+        self.v0.check_realmoji_files()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::fs;
+
+    #[test]
+    fn resolves_by_alternative_extension() {
+        let dir = TestDir::new("v1");
+        fs::create_dir_all(dir.join("Photos/bereal")).unwrap();
+        fs::write(dir.join("Photos/bereal/a.webp"), b"").unwrap();
+        fs::write(dir.join("Photos/bereal/b.jpg.webp"), b"").unwrap();
+
+        let parser = ParserV1::new(Arc::new(InputSource::Directory(dir.to_path_buf())));
+        assert_eq!(
+            parser.resolve(&PathBuf::from("./Photos/bereal/a.jpg")),
+            Some(PathBuf::from("./Photos/bereal/a.webp"))
+        );
+        assert_eq!(
+            parser.resolve(&PathBuf::from("./Photos/bereal/b.jpg")),
+            Some(PathBuf::from("./Photos/bereal/b.jpg.webp"))
+        );
+        assert_eq!(
+            parser.resolve(&PathBuf::from("./Photos/bereal/c.jpg")),
+            None
+        );
+    }
+}
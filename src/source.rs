@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use zip::ZipArchive;
+
+use crate::error::BerealError;
+
+/// Where the BeReal dump is read from - either an unzipped folder or the downloaded archive itself.
+///
+/// All paths passed to the methods are relative to the root of the dump
+/// (the folder containing `memories.json`), e.g. `./Photos/post/xyz.webp`.
+pub enum InputSource {
+    Directory(PathBuf),
+    Archive {
+        path: PathBuf,
+        /// prefix of the dump root inside the archive, either empty or ending with `/`
+        root: String,
+        /// normalized entry name -> indices of all entries with that name
+        /// (the dump contains duplicate entries, e.g. `realmojis.json`)
+        entries: HashMap<String, Vec<usize>>,
+        // ZipArchive needs &mut for reading, each export thread takes an idle one (or opens another)
+        archives: Mutex<Vec<ZipArchive<File>>>,
+    },
+}
+
+impl InputSource {
+    const ROOT_MARKERS: [&'static str; 2] = ["memories.json", "user.json"];
+
+    pub fn open(path: &Path) -> Result<InputSource, BerealError> {
+        if path.is_dir() {
+            return Ok(InputSource::Directory(path.to_path_buf()));
+        }
+        if !path.is_file() {
+            return Err(BerealError::InvalidInput {
+                message: format!("{} is neither a folder nor a file", path.to_string_lossy()),
+            });
+        }
+
+        let mut archive = open_archive(path)?;
+
+        let mut entries: HashMap<String, Vec<usize>> = HashMap::new();
+        for i in 0..archive.len() {
+            let entry = archive
+                .by_index_raw(i)
+                .map_err(|e| BerealError::InvalidInput {
+                    message: format!("corrupted zip archive {} - {}", path.to_string_lossy(), e),
+                })?;
+            if entry.is_file() {
+                entries
+                    .entry(normalize_entry_name(entry.name()))
+                    .or_default()
+                    .push(i);
+            }
+        }
+
+        Ok(InputSource::Archive {
+            path: path.to_path_buf(),
+            root: find_archive_root(entries.keys()),
+            entries,
+            archives: Mutex::new(vec![archive]),
+        })
+    }
+
+    /// path used in user-facing messages
+    pub fn display_path(&self, relative: &Path) -> PathBuf {
+        match self {
+            InputSource::Directory(dir) => dir.join(relative),
+            InputSource::Archive { path, root, .. } => {
+                path.join(root.clone() + &relative_to_entry_name(relative))
+            }
+        }
+    }
+
+    pub fn is_dir(&self, relative: &Path) -> bool {
+        match self {
+            InputSource::Directory(dir) => dir.join(relative).is_dir(),
+            InputSource::Archive { root, entries, .. } => {
+                let prefix = root.clone() + &relative_to_entry_name(relative) + "/";
+                entries.keys().any(|name| name.starts_with(&prefix))
+            }
+        }
+    }
+
+    pub fn is_file(&self, relative: &Path) -> bool {
+        match self {
+            InputSource::Directory(dir) => dir.join(relative).is_file(),
+            InputSource::Archive { .. } => !self.archive_indices(relative).is_empty(),
+        }
+    }
+
+    /// reads the file, in case of duplicate archive entries, the first one is read
+    pub fn read(&self, relative: &Path) -> Result<Vec<u8>, BerealError> {
+        self.read_all(relative)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| self.missing_error(relative))
+    }
+
+    /// reads all files of the same name
+    /// (only archives can contain more than one, folders always yield a single item)
+    pub fn read_all(&self, relative: &Path) -> Result<Vec<Vec<u8>>, BerealError> {
+        match self {
+            InputSource::Directory(dir) => {
+                let path = dir.join(relative);
+                fs::read(&path)
+                    .map(|v| vec![v])
+                    .map_err(|e| BerealError::io(&path, e))
+            }
+            InputSource::Archive { .. } => {
+                let indices = self.archive_indices(relative);
+                if indices.is_empty() {
+                    return Err(self.missing_error(relative));
+                }
+                indices
+                    .into_iter()
+                    .map(|i| {
+                        let mut buf = vec![];
+                        self.with_archive_entry(i, |entry| entry.read_to_end(&mut buf))
+                            .map(|_| buf)
+                            .map_err(|e| BerealError::io(&self.display_path(relative), e))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn archive_indices(&self, relative: &Path) -> Vec<usize> {
+        match self {
+            InputSource::Directory(_) => vec![],
+            InputSource::Archive { root, entries, .. } => entries
+                .get(&(root.clone() + &relative_to_entry_name(relative)))
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn with_archive_entry<T, F>(&self, index: usize, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> io::Result<T>,
+    {
+        match self {
+            InputSource::Directory(_) => Err(io::Error::other("not an archive")),
+            InputSource::Archive { path, archives, .. } => {
+                let lock = || {
+                    archives
+                        .lock()
+                        .map_err(|_| io::Error::other("archive lock poisoned"))
+                };
+                // the lock is not held while decompressing
+                let idle = lock()?.pop();
+                let mut archive = match idle {
+                    Some(archive) => archive,
+                    None => open_archive(path).map_err(|e| io::Error::other(e.to_string()))?,
+                };
+                let result = match archive.by_index(index) {
+                    Ok(mut entry) => f(&mut entry),
+                    Err(e) => Err(e.into()),
+                };
+                lock()?.push(archive);
+                result
+            }
+        }
+    }
+
+    fn missing_error(&self, relative: &Path) -> BerealError {
+        BerealError::MissingFile {
+            path: self.display_path(relative),
+        }
+    }
+}
+
+fn open_archive(path: &Path) -> Result<ZipArchive<File>, BerealError> {
+    let file = File::open(path).map_err(|e| BerealError::io(path, e))?;
+    ZipArchive::new(file).map_err(|e| BerealError::InvalidInput {
+        message: format!("{} is not a zip archive - {}", path.to_string_lossy(), e),
+    })
+}
+
+fn normalize_entry_name(name: &str) -> String {
+    name.replace('\\', "/")
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+fn relative_to_entry_name(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// the dump may be zipped as-is or wrapped in a single folder,
+/// the root is where the top-most json marker file is located
+fn find_archive_root<'a, I>(names: I) -> String
+where
+    I: Iterator<Item = &'a String>,
+{
+    names
+        .filter(|n| {
+            InputSource::ROOT_MARKERS
+                .iter()
+                .any(|marker| *n == marker || n.ends_with(&format!("/{marker}")))
+        })
+        .min_by_key(|n| n.matches('/').count())
+        .map(|n| match n.rfind('/') {
+            Some(i) => n[..=i].to_string(),
+            None => "".to_string(),
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn normalizes_entry_names() {
+        assert_eq!(
+            normalize_entry_name("./Photos//post/a.webp"),
+            "Photos/post/a.webp"
+        );
+        assert_eq!(normalize_entry_name("/memories.json"), "memories.json");
+    }
+
+    #[test]
+    fn relative_paths_match_entry_names() {
+        assert_eq!(
+            relative_to_entry_name(Path::new("./Photos/post/a.webp")),
+            "Photos/post/a.webp"
+        );
+    }
+
+    #[test]
+    fn finds_archive_root() {
+        let flat = [
+            "memories.json".to_string(),
+            "Photos/post/a.webp".to_string(),
+        ];
+        assert_eq!(find_archive_root(flat.iter()), "");
+
+        let nested = [
+            "dump/Photos/post/memories.json".to_string(),
+            "dump/user.json".to_string(),
+            "dump/memories.json".to_string(),
+        ];
+        assert_eq!(find_archive_root(nested.iter()), "dump/");
+    }
+
+    #[test]
+    fn reads_all_duplicate_archive_entries() {
+        use std::io::Write;
+
+        let dir = TestDir::new("source");
+        let zip_path = dir.join("export.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, content) in [
+            ("dump/memories.json", "[]"),
+            ("dump/realmojis.json", "[1]"),
+            ("dump/realmojis.json", "[2]"),
+        ] {
+            zip.start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let input = InputSource::open(&zip_path).unwrap();
+        let payloads = input.read_all(Path::new("./realmojis.json")).unwrap();
+        assert_eq!(payloads, vec![b"[1]".to_vec(), b"[2]".to_vec()]);
+    }
+}
@@ -0,0 +1,332 @@
+//! Folder and file name templates, e.g. `{year}/{month:02}-{month_name}/{date}_{time}_{camera}{late?_late}`.
+//!
+//! `/` separates folders, the last part is the file name (without the extension).
+//! `{name}` inserts a value, `{name:02}` a zero-padded number and `{flag?text}` or `{flag?text:other}`
+//! a text depending on a flag.
+
+use std::path::PathBuf;
+
+use crate::error::BerealError;
+
+/// file part of a moment (e.g. `camera_front`, `BTS`), filled in for each exported file
+pub const CAMERA_PLACEHOLDER: &str = "{camera}";
+
+/// placeholders known to a template, by kind
+pub struct Placeholders {
+    pub numbers: &'static [&'static str],
+    pub texts: &'static [&'static str],
+    pub flags: &'static [&'static str],
+}
+
+pub const MOMENT_PLACEHOLDERS: Placeholders = Placeholders {
+    numbers: &[
+        "year",
+        "month",
+        "day",
+        "hour",
+        "minute",
+        "second",
+        "week",
+        "week_year",
+    ],
+    texts: &["month_name", "weekday", "date", "time", "camera"],
+    flags: &["late", "bts", "song"],
+};
+
+pub const REALMOJI_PLACEHOLDERS: Placeholders = Placeholders {
+    numbers: &[
+        "year",
+        "month",
+        "day",
+        "hour",
+        "minute",
+        "second",
+        "week",
+        "week_year",
+        "index",
+    ],
+    texts: &["month_name", "weekday", "date", "time", "emoji"],
+    flags: &["instant"],
+};
+
+pub enum Value {
+    Number(i64),
+    Text(String),
+    Flag(bool),
+}
+
+/// provides the values of the placeholders for a single item
+pub trait TemplateValues {
+    fn value(&self, name: &str) -> Value;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Value {
+        name: String,
+        width: Option<usize>,
+    },
+    Flag {
+        name: String,
+        then: String,
+        otherwise: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(template: &str, placeholders: &Placeholders) -> Result<Template, BerealError> {
+        let invalid = |message: String| BerealError::InvalidArgument {
+            message: format!("template `{template}`: {message}"),
+        };
+
+        let mut segments = vec![];
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix('{') {
+                let end = inner
+                    .find('}')
+                    .ok_or_else(|| invalid("unclosed `{`".to_owned()))?;
+                let placeholder = &inner[..end];
+                if placeholder.contains('{') {
+                    return Err(invalid("unclosed `{`".to_owned()));
+                }
+                segments.push(parse_placeholder(placeholder, placeholders).map_err(invalid)?);
+                rest = &inner[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let text = &rest[..end];
+                if text.contains('}') {
+                    return Err(invalid("unexpected `}`".to_owned()));
+                }
+                segments.push(Segment::Text(text.to_owned()));
+                rest = &rest[end..];
+            }
+        }
+
+        if template.starts_with('/') {
+            return Err(invalid("must be relative to the output folder".to_owned()));
+        }
+        if template.split('/').any(|part| part.trim() == "..") {
+            return Err(invalid("must not leave the output folder".to_owned()));
+        }
+        // segments after the last folder separator
+        let file_name_start = segments
+            .iter()
+            .rposition(|s| matches!(s, Segment::Text(text) if text.contains('/')))
+            .map_or(0, |i| i + 1);
+        let file_name = &segments[file_name_start..];
+        let last_text = match segments.get(file_name_start.wrapping_sub(1)) {
+            Some(Segment::Text(text)) => text.rsplit('/').next().unwrap_or_default(),
+            _ => "",
+        };
+        if last_text.trim().is_empty()
+            && !file_name.iter().any(|s| match s {
+                Segment::Text(text) => !text.trim().is_empty(),
+                Segment::Value { .. } => true,
+                Segment::Flag { .. } => false,
+            })
+        {
+            return Err(invalid("the file name is empty".to_owned()));
+        }
+        let camera = segments
+            .iter()
+            .position(|s| matches!(s, Segment::Value { name, .. } if name == "camera"));
+        if let Some(camera) = camera {
+            let in_folder = segments[camera..].iter().any(|s| match s {
+                Segment::Text(text) => text.contains('/'),
+                Segment::Flag {
+                    then, otherwise, ..
+                } => then.contains('/') || otherwise.contains('/'),
+                Segment::Value { .. } => false,
+            });
+            if in_folder {
+                return Err(invalid(
+                    "{camera} can only be used in the file name".to_owned(),
+                ));
+            }
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// renders the folder (relative to the output folder) and the file name prefix of an item
+    pub fn render(&self, values: &impl TemplateValues) -> (PathBuf, String) {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered += &sanitize(text, true),
+                Segment::Value { name, .. } if name == "camera" => rendered += CAMERA_PLACEHOLDER,
+                Segment::Value { name, width } => match values.value(name) {
+                    Value::Number(n) => {
+                        rendered += &format!("{:0width$}", n, width = width.unwrap_or(0))
+                    }
+                    Value::Text(text) => rendered += &sanitize_value(&text),
+                    Value::Flag(flag) => rendered += &flag.to_string(),
+                },
+                Segment::Flag {
+                    name,
+                    then,
+                    otherwise,
+                } => {
+                    let set = matches!(values.value(name), Value::Flag(true));
+                    rendered += &sanitize(if set { then } else { otherwise }, true);
+                }
+            }
+        }
+
+        let mut parts = rendered
+            .split('/')
+            .map(|part| part.trim().trim_end_matches('.'))
+            .filter(|part| !part.is_empty() && *part != ".")
+            .map(|part| part.to_owned())
+            .collect::<Vec<_>>();
+        let file_name = parts.pop().unwrap_or_else(|| "_".to_owned());
+        (parts.into_iter().collect(), file_name)
+    }
+}
+
+fn parse_placeholder(placeholder: &str, placeholders: &Placeholders) -> Result<Segment, String> {
+    if let Some((name, texts)) = placeholder.split_once('?') {
+        if !placeholders.flags.contains(&name) {
+            return Err(unknown(name, placeholders));
+        }
+        let (then, otherwise) = texts.split_once(':').unwrap_or((texts, ""));
+        return Ok(Segment::Flag {
+            name: name.to_owned(),
+            then: then.to_owned(),
+            otherwise: otherwise.to_owned(),
+        });
+    }
+
+    let (name, width) = match placeholder.split_once(':') {
+        Some((name, format)) => {
+            let width = format
+                .parse::<usize>()
+                .ok()
+                .filter(|_| format.chars().all(|c| c.is_ascii_digit()))
+                .ok_or_else(|| {
+                    format!("invalid format `{format}` of {{{name}}}, expected e.g. 02")
+                })?;
+            if !placeholders.numbers.contains(&name) {
+                return Err(format!("{{{name}}} is not a number, it cannot be padded"));
+            }
+            (name, Some(width))
+        }
+        None => (placeholder, None),
+    };
+    if placeholders.flags.contains(&name) {
+        return Err(format!(
+            "{{{name}}} is a flag, use e.g. {{{name}?text}} or {{{name}?text:other}}"
+        ));
+    }
+    if !placeholders.numbers.contains(&name) && !placeholders.texts.contains(&name) {
+        return Err(unknown(name, placeholders));
+    }
+    Ok(Segment::Value {
+        name: name.to_owned(),
+        width,
+    })
+}
+
+fn unknown(name: &str, placeholders: &Placeholders) -> String {
+    let known = placeholders
+        .numbers
+        .iter()
+        .chain(placeholders.texts)
+        .chain(placeholders.flags)
+        .map(|p| format!("{{{p}}}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("unknown placeholder {{{name}}}, expected one of {known}")
+}
+
+/// replaces characters not allowed in file names (on any platform)
+fn sanitize(text: &str, keep_separator: bool) -> String {
+    text.chars()
+        .map(|c| match c {
+            '/' if keep_separator => '/',
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '{' | '}' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect()
+}
+
+fn sanitize_value(text: &str) -> String {
+    let value = sanitize(text, false);
+    let value = value.trim().trim_end_matches('.');
+    if value.is_empty() || value == "." {
+        "_".to_owned()
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Moment;
+
+    impl TemplateValues for Moment {
+        fn value(&self, name: &str) -> Value {
+            match name {
+                "year" => Value::Number(2024),
+                "month" => Value::Number(1),
+                "month_name" => Value::Text("January".to_owned()),
+                "date" => Value::Text("2024-01-07".to_owned()),
+                "time" => Value::Text("07-00-00".to_owned()),
+                "weekday" => Value::Text("a/b:c".to_owned()),
+                "late" => Value::Flag(true),
+                _ => Value::Flag(false),
+            }
+        }
+    }
+
+    #[test]
+    fn renders_folders_and_file_name() {
+        let template = Template::parse(
+            "{year}/{month:02}-{month_name}/{date}_{time}_{camera}{late?_late}{bts?_bts:}",
+            &MOMENT_PLACEHOLDERS,
+        )
+        .unwrap();
+        let (folder, name) = template.render(&Moment);
+        assert_eq!(folder, PathBuf::from("2024/01-January"));
+        assert_eq!(name, "2024-01-07_07-00-00_{camera}_late");
+
+        let (folder, name) =
+            Template::parse("{late?late/:on-time/}{weekday}", &MOMENT_PLACEHOLDERS)
+                .unwrap()
+                .render(&Moment);
+        assert_eq!((folder, name.as_str()), (PathBuf::from("late"), "a-b-c"));
+
+        assert!(Template::parse("{week_year}/W{week:02}/{index}", &REALMOJI_PLACEHOLDERS).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in [
+            "{year}/{nope}",
+            "{year:x}",
+            "{date:02}",
+            "{late}",
+            "{year",
+            "year}",
+            "/{year}",
+            "../{year}",
+            "{year}/",
+            "{camera}/{date}",
+        ] {
+            assert!(
+                Template::parse(template, &MOMENT_PLACEHOLDERS).is_err(),
+                "{template}"
+            );
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Temporary folders for tests, removed when dropped (also when the test panics).
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// an empty folder unique to the test, `name` tells the tests apart when inspecting leftovers
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!(
+            "bereal-{name}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        // left over from an earlier run with the same process id
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,120 @@
+//! XMP sidecar files, for tools that do not read the embedded metadata.
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::BerealLocation;
+
+pub struct Sidecar {
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub created: DateTime<FixedOffset>,
+    pub location: Option<BerealLocation>,
+    /// file names of the other files exported from the same item
+    pub related: Vec<String>,
+}
+
+impl Sidecar {
+    pub fn to_xml(&self) -> String {
+        let mut properties = vec![];
+        if let Some(description) = self.description.as_ref().filter(|d| !d.is_empty()) {
+            properties.push(format!(
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+                escape(description)
+            ));
+        }
+        if !self.keywords.is_empty() {
+            properties.push(format!("<dc:subject>{}</dc:subject>", bag(&self.keywords)));
+        }
+        if !self.related.is_empty() {
+            properties.push(format!("<dc:relation>{}</dc:relation>", bag(&self.related)));
+        }
+        let created = self.created.to_rfc3339();
+        properties.push(format!("<xmp:CreateDate>{created}</xmp:CreateDate>"));
+        properties.push(format!(
+            "<photoshop:DateCreated>{created}</photoshop:DateCreated>"
+        ));
+        properties.push(format!(
+            "<exif:DateTimeOriginal>{created}</exif:DateTimeOriginal>"
+        ));
+        if let Some(location) = &self.location {
+            properties.push(format!(
+                "<exif:GPSLatitude>{}</exif:GPSLatitude>",
+                gps_coordinate(location.latitude, ['N', 'S'])
+            ));
+            properties.push(format!(
+                "<exif:GPSLongitude>{}</exif:GPSLongitude>",
+                gps_coordinate(location.longitude, ['E', 'W'])
+            ));
+        }
+
+        let mut xml = String::from(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+             \x20<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+             \x20 <rdf:Description rdf:about=\"\"\n\
+             \x20   xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n\
+             \x20   xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n\
+             \x20   xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"\n\
+             \x20   xmlns:exif=\"http://ns.adobe.com/exif/1.0/\">\n",
+        );
+        for property in properties {
+            xml += "   ";
+            xml += &property;
+            xml += "\n";
+        }
+        xml += "  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n";
+        xml
+    }
+}
+
+fn bag(items: &[String]) -> String {
+    let items = items
+        .iter()
+        .map(|item| format!("<rdf:li>{}</rdf:li>", escape(item)))
+        .collect::<String>();
+    format!("<rdf:Bag>{items}</rdf:Bag>")
+}
+
+/// XMP GPS format: degrees, decimal minutes and the direction, e.g. `50,4.5300N`
+fn gps_coordinate(value: f64, [positive, negative]: [char; 2]) -> String {
+    let direction = if value < 0.0 { negative } else { positive };
+    let value = value.abs();
+    let degrees = value.floor();
+    format!("{},{:.4}{direction}", degrees, (value - degrees) * 60.0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_escaped_properties() {
+        let sidecar = Sidecar {
+            description: Some("fish & <chips>".to_owned()),
+            keywords: vec!["late".to_owned(), "food".to_owned()],
+            created: DateTime::parse_from_rfc3339("2024-01-07T07:00:00-05:00").unwrap(),
+            location: Some(BerealLocation {
+                latitude: 50.0755,
+                longitude: -14.42,
+            }),
+            related: vec!["a_camera_back.jpeg".to_owned()],
+        };
+        let xml = sidecar.to_xml();
+
+        assert!(xml.contains("<rdf:li xml:lang=\"x-default\">fish &amp; &lt;chips&gt;</rdf:li>"));
+        assert!(xml.contains(
+            "<dc:subject><rdf:Bag><rdf:li>late</rdf:li><rdf:li>food</rdf:li></rdf:Bag></dc:subject>"
+        ));
+        assert!(xml.contains("<rdf:li>a_camera_back.jpeg</rdf:li>"));
+        assert!(xml.contains("<xmp:CreateDate>2024-01-07T07:00:00-05:00</xmp:CreateDate>"));
+        assert!(xml.contains("<exif:GPSLatitude>50,4.5300N</exif:GPSLatitude>"));
+        assert!(xml.contains("<exif:GPSLongitude>14,25.2000W</exif:GPSLongitude>"));
+    }
+}